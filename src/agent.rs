@@ -0,0 +1,478 @@
+//! An `ngrok` process serving several tunnels, which are created and stopped through
+//! its JSON API instead of spawning a process per tunnel.
+
+use crate::api::{self, AgentApi, TunnelInfo};
+use crate::{
+    spawn, AgentVersion, Builder, Error, Lookup, Process, PublicUrl, Region, RootCas, Secret,
+    StderrTail, TempFile, Tunnel, WithPort, DEFAULT_WEB_ADDR,
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::time::{Duration, Instant};
+use std::{io, thread};
+use url::Url;
+
+/// The agents shared by `Builder::shared_agent()` tunnels in this process, one per set
+/// of agent-wide options, which are stopped when the last of their tunnels is dropped
+static SHARED: Mutex<Vec<SharedAgent>> = Mutex::new(Vec::new());
+
+/// Notified once a shared agent has started, or failed to
+static SHARED_STARTED: Condvar = Condvar::new();
+
+#[derive(Debug)]
+struct SharedAgent {
+    options: AgentOptions,
+    /// `None` while the agent is starting
+    inner: Option<Weak<Inner>>,
+}
+
+/// The options which apply to an agent as a whole, so that tunnels only share an agent
+/// started with the same ones
+#[derive(Debug, Clone, PartialEq)]
+struct AgentOptions {
+    executable: PathBuf,
+    authtoken: Option<Secret>,
+    config_files: Vec<PathBuf>,
+    region: Option<Region>,
+    server_addr: Option<String>,
+    root_cas: Option<RootCas>,
+    proxy_url: Option<Secret>,
+    heartbeat_interval: Option<Duration>,
+    heartbeat_tolerance: Option<Duration>,
+}
+
+impl AgentOptions {
+    fn of<S>(builder: &Builder<S>) -> Self {
+        let options = &builder.options;
+
+        AgentOptions {
+            executable: builder.program(),
+            authtoken: options.authtoken.clone(),
+            config_files: options.config_files.clone(),
+            region: options.region,
+            server_addr: options.server_addr.clone(),
+            root_cas: options.root_cas.clone(),
+            proxy_url: options.proxy_url.clone(),
+            heartbeat_interval: options.heartbeat_interval,
+            heartbeat_tolerance: options.heartbeat_tolerance,
+        }
+    }
+}
+
+/// A running `ngrok` agent which serves several tunnels, e.g. HTTP on 3000 and TCP on
+/// 5432. Free accounts only allow one agent session at a time, so use this rather than
+/// `Builder::run()` to open more than one tunnel. Clones share the agent, which is
+/// stopped once it and all of its tunnels are dropped.
+///
+/// **Example**
+///
+/// ```no_run
+/// let agent = ngrok::Agent::start(ngrok::builder().authtoken("..."))?;
+///
+/// let web = agent.tunnel(ngrok::builder().https().port(3000))?;
+/// let db = agent.tunnel(ngrok::builder().tcp().port(5432))?;
+///
+/// println!("{} and {}", web, db);
+/// # Ok::<(), ngrok::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Agent {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    proc: Mutex<Process>,
+    version: AgentVersion,
+    api: AgentApi,
+    stderr: StderrTail,
+    _config: TempFile,
+}
+
+impl Agent {
+    /// Start an agent without tunnels and wait for its API to come up. Only the
+    /// builder's agent-wide options are used: the executable, config files, authtoken,
+    /// region, server address, root CAs, proxy, heartbeat, web address, ready timeout
+    /// and backoff.
+    pub fn start<S>(builder: Builder<S>) -> Result<Agent, Error> {
+        Agent::launch(builder, "none")
+    }
+
+    /// Start an agent with every tunnel defined in its config, see
+    /// `Builder::start_all()`, and wait for them to come up
+    pub(crate) fn start_all<S>(builder: Builder<S>) -> Result<HashMap<String, Tunnel>, Error> {
+        let options = builder.options.clone();
+        let mut delays = options.backoff().delays();
+        let agent = Agent::launch(builder, "all")?;
+        let started_at = Instant::now();
+
+        // The agent opens the tunnels one by one, so wait for those the configs define.
+        // If there are none to be found, wait for the list to stop growing instead.
+        let expected = options.configured_tunnels(agent.inner.version);
+        let mut listed = Vec::new();
+        let tunnels = loop {
+            let tunnels: Vec<TunnelInfo> = agent
+                .tunnels()?
+                .into_iter()
+                .filter(|tunnel| !tunnel.public_url.is_empty())
+                .collect();
+
+            let started = match &expected[..] {
+                [] => !tunnels.is_empty() && tunnels == listed,
+                expected => expected
+                    .iter()
+                    .all(|name| tunnels.iter().any(|tunnel| &tunnel.name == name)),
+            };
+            if started {
+                break tunnels;
+            }
+
+            agent.status()?;
+
+            if started_at.elapsed() > options.ready_timeout() {
+                match tunnels.is_empty() || !expected.is_empty() {
+                    true => return Err(Error::TunnelNotFound),
+                    false => break tunnels,
+                }
+            }
+
+            listed = tunnels;
+            thread::sleep(delays.next_delay());
+        };
+
+        let mut started = HashMap::new();
+        for tunnel in tunnels {
+            // A v2 agent lists the HTTP URL of an HTTPS tunnel as `<name> (http)`
+            if tunnel.name.ends_with(" (http)") {
+                continue;
+            }
+            let public_url =
+                Url::parse(&tunnel.public_url).map_err(|_| Error::MalformedAPIResponse)?;
+            started.insert(tunnel.name.clone(), agent.remote(tunnel.name, public_url));
+        }
+
+        Ok(started)
+    }
+
+    /// Run `ngrok start` with `--none` or `--all` and wait for the agent's API to come up
+    fn launch<S>(builder: Builder<S>, tunnels: &str) -> Result<Agent, Error> {
+        builder.options.check_session_hooks()?;
+        let builder = builder.with_env_defaults()?.with_free_web_addr()?;
+        let options = &builder.options;
+        let executable = &builder.program();
+
+        let version = options.detect_version(executable)?;
+        options.check_authtoken(version)?;
+
+        let web_addr = options.web_addr.unwrap_or(DEFAULT_WEB_ADDR);
+        let config = TempFile::write("ngrok.yml", &options.config(version, web_addr))?;
+
+        let args = options.start_args(version, config.path(), &[&version.flag(tunnels, "true")])?;
+
+        let mut command = Command::new(executable);
+        command
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .args(args);
+        let mut proc = spawn(command, true)?;
+        let stderr = StderrTail::watch(proc.stderr.take().unwrap(), options.log_stderr);
+
+        let agent = Agent {
+            inner: Arc::new(Inner {
+                proc: Mutex::new(Process::Std(proc)),
+                version,
+                api: options.api(web_addr),
+                stderr,
+                _config: config,
+            }),
+        };
+
+        let started_at = Instant::now();
+        let mut delays = options.backoff().delays();
+
+        loop {
+            let ready = agent.inner.api.get("/api/tunnels");
+            if ready.is_ok() {
+                return Ok(agent);
+            }
+
+            agent.status()?;
+            // The agent is stopped once dropped
+            options.check_cancelled()?;
+
+            if started_at.elapsed() > options.ready_timeout() {
+                return ready.map(|_| agent);
+            }
+
+            thread::sleep(delays.next_delay());
+        }
+    }
+
+    /// The agent shared by the process for the builder's agent-wide options, started
+    /// with them unless it's already running
+    pub(crate) fn shared<S: Clone>(builder: &Builder<S>) -> Result<Agent, Error> {
+        let options = AgentOptions::of(builder);
+        let mut shared = SHARED.lock().unwrap();
+
+        loop {
+            shared.retain(|agent| {
+                agent
+                    .inner
+                    .as_ref()
+                    .is_none_or(|inner| inner.strong_count() > 0)
+            });
+
+            match shared.iter().find(|agent| agent.options == options) {
+                Some(SharedAgent { inner: None, .. }) => {
+                    shared = SHARED_STARTED.wait(shared).unwrap();
+                }
+                Some(SharedAgent {
+                    inner: Some(inner), ..
+                }) => match inner.upgrade() {
+                    Some(inner) => return Ok(Agent { inner }),
+                    None => continue,
+                },
+                None => break,
+            }
+        }
+
+        // Other shared agents can be used while this one starts
+        shared.push(SharedAgent {
+            options: options.clone(),
+            inner: None,
+        });
+        drop(shared);
+
+        let agent = Agent::start(builder.clone());
+
+        let mut shared = SHARED.lock().unwrap();
+        shared.retain(|agent| agent.options != options);
+        if let Ok(agent) = &agent {
+            shared.push(SharedAgent {
+                options,
+                inner: Some(Arc::downgrade(&agent.inner)),
+            });
+        }
+        SHARED_STARTED.notify_all();
+
+        agent
+    }
+
+    /// Open the builder's tunnel on this agent. Its agent-wide options are ignored.
+    pub fn tunnel(&self, builder: Builder<WithPort>) -> Result<Tunnel, Error> {
+        static TUNNELS: AtomicUsize = AtomicUsize::new(0);
+
+        builder.options.check_session_hooks()?;
+
+        let name = format!(
+            "ngrok-{}-{}",
+            std::process::id(),
+            TUNNELS.fetch_add(1, Ordering::Relaxed)
+        );
+
+        builder.wait_for_backend_ready()?;
+        let config = builder.tunnel_config(self.inner.version, &name)?;
+        let query = builder.query()?;
+        let started_at = Instant::now();
+
+        let response = self.inner.api.post("/api/tunnels", &config)?;
+        let tunnel = TunnelInfo::deserialize(response).map_err(|_| Error::MalformedAPIResponse)?;
+
+        let tunnel = self.remote(name, query.find(std::slice::from_ref(&tunnel))?);
+        builder
+            .options
+            .wait_until_ready(&tunnel.current_url_unchecked(), started_at, || {
+                self.status()
+            })?;
+        Ok(tunnel)
+    }
+
+    /// A handle to the named tunnel of this agent, which is stopped through the API
+    fn remote(&self, name: String, public_url: Url) -> Tunnel {
+        let proc = Process::Remote {
+            agent: self.clone(),
+            name: name.clone(),
+        };
+
+        Tunnel {
+            proc: Arc::new(Mutex::new(proc)),
+            _files: Default::default(),
+            stderr: Arc::new(Mutex::new(self.inner.stderr.clone())),
+            events: Default::default(),
+            stopped: Default::default(),
+            spec: Default::default(),
+            public_url: PublicUrl::new(public_url, self.inner.api.clone(), Lookup::Name(name)),
+            api: self.inner.api.clone(),
+        }
+    }
+
+    /// The address of the agent's web interface and API
+    pub fn web_addr(&self) -> SocketAddr {
+        self.inner.api.web_addr()
+    }
+
+    /// The agent's live tunnels, including those not created by this crate
+    pub fn tunnels(&self) -> Result<Vec<TunnelInfo>, Error> {
+        self.inner.api.list_tunnels()
+    }
+
+    /// Determine if the agent has exited and return the exit error if so
+    pub fn status(&self) -> Result<(), Error> {
+        match self.try_wait()? {
+            Some(status) => Err(self.inner.stderr.exited(status)),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn stop_tunnel(&self, name: &str) -> Result<(), Error> {
+        stop_tunnel(&self.inner.api, name)
+    }
+
+    pub(crate) fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        self.inner.proc.lock().unwrap().try_wait()
+    }
+}
+
+/// Stop a tunnel through the agent's API, leaving the agent running
+pub(crate) fn stop_tunnel(api: &AgentApi, name: &str) -> Result<(), Error> {
+    api.delete(&api::tunnel_path(name))
+}
+
+/// The number of connections open on the agent, through the named tunnel or all of them
+pub(crate) fn open_connections(api: &AgentApi, name: Option<&str>) -> Result<u64, Error> {
+    count_connections(&api.get("/api/tunnels")?, name)
+}
+
+/// Sum the `conns` gauges of the tunnels listed by the agent API
+fn count_connections(response: &serde_json::Value, name: Option<&str>) -> Result<u64, Error> {
+    let count = api::tunnels(response)?
+        .iter()
+        .filter(|tunnel| name.is_none() || Some(tunnel.name.as_str()) == name)
+        .map(|tunnel| tunnel.metrics.conns.gauge)
+        .sum();
+
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ApiResponse, ApiTransport};
+    use serde_json::json;
+
+    /// Answers every request with an empty list of tunnels
+    #[derive(Debug)]
+    struct NoTunnels;
+
+    impl ApiTransport for NoTunnels {
+        fn send(
+            &self,
+            _method: &str,
+            _url: &Url,
+            _body: Option<&serde_json::Value>,
+        ) -> Result<ApiResponse, Error> {
+            Ok(ApiResponse {
+                status: 200,
+                body: r#"{"tunnels":[]}"#.to_string(),
+            })
+        }
+    }
+
+    /// Lists the `web` tunnel for a few requests before the `db` one comes up too
+    #[derive(Debug, Default)]
+    struct StaggeredTunnels(AtomicUsize);
+
+    impl ApiTransport for StaggeredTunnels {
+        fn send(
+            &self,
+            _method: &str,
+            _url: &Url,
+            _body: Option<&serde_json::Value>,
+        ) -> Result<ApiResponse, Error> {
+            let tunnel =
+                |name| json!({ "name": name, "public_url": format!("tcp://{}.ngrok.io:1", name) });
+            let tunnels = match self.0.fetch_add(1, Ordering::SeqCst) {
+                0..=3 => vec![tunnel("web")],
+                _ => vec![tunnel("web"), tunnel("db")],
+            };
+
+            Ok(ApiResponse {
+                status: 200,
+                body: json!({ "tunnels": tunnels }).to_string(),
+            })
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start_all_configured_tunnels() {
+        let ngrok = crate::tests::fake_agent("exec sleep 30");
+        let config = ngrok.with_file_name("ngrok.yml");
+        std::fs::write(
+            &config,
+            "tunnels:\n  web:\n    proto: tcp\n    addr: 3000\n  db:\n    proto: tcp\n    addr: 5432\n",
+        )
+        .unwrap();
+
+        let tunnels = crate::builder()
+            .executable(&ngrok)
+            .authtoken("fake")
+            .config_file(&config)
+            .backoff(crate::Backoff::fixed(std::time::Duration::from_millis(10)))
+            .api_transport(StaggeredTunnels::default())
+            .start_all()
+            .unwrap();
+        let mut names: Vec<_> = tunnels.keys().collect();
+        names.sort();
+        assert_eq!(names, ["db", "web"]);
+
+        std::fs::remove_dir_all(ngrok.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_shared_agent_options() {
+        let ngrok = crate::tests::fake_agent("exec sleep 30");
+        let builder = crate::builder()
+            .executable(&ngrok)
+            .authtoken("fake")
+            .api_transport(NoTunnels);
+
+        let agent = Agent::shared(&builder).unwrap();
+        let same = Agent::shared(&builder.clone().https()).unwrap();
+        assert!(Arc::ptr_eq(&agent.inner, &same.inner));
+
+        // Agent-wide options which differ get an agent of their own
+        let other = Agent::shared(&builder.clone().region(Region::Eu)).unwrap();
+        assert!(!Arc::ptr_eq(&agent.inner, &other.inner));
+        let trusting = Agent::shared(&builder.clone().root_cas(RootCas::Host)).unwrap();
+        assert!(!Arc::ptr_eq(&agent.inner, &trusting.inner));
+
+        // The agent's log isn't read for the tunnels opened on it
+        let err = agent
+            .tunnel(crate::builder().on_reconnect(|| {}).port(3030))
+            .unwrap_err();
+        assert!(matches!(err, Error::Builder(_)), "{:?}", err);
+
+        std::fs::remove_dir_all(ngrok.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_count_connections() {
+        let response = json!({
+            "tunnels": [
+                { "name": "web", "metrics": { "conns": { "count": 10, "gauge": 2 } } },
+                { "name": "db", "metrics": { "conns": { "count": 3, "gauge": 1 } } }
+            ]
+        });
+        assert_eq!(count_connections(&response, None).unwrap(), 3);
+        assert_eq!(count_connections(&response, Some("db")).unwrap(), 1);
+        assert_eq!(count_connections(&response, Some("other")).unwrap(), 0);
+        assert!(count_connections(&json!({}), None).is_err());
+    }
+}
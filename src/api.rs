@@ -0,0 +1,642 @@
+//! Everything the agent's API at `/api/tunnels` reports about a tunnel. Fetch it with
+//! `Tunnel::info()`, or for every tunnel of an agent with `ngrok::list_tunnels()`.
+//!
+//! **Example**
+//!
+//! ```no_run
+//! let tunnel = ngrok::builder().https().port(3030).run()?;
+//!
+//! let info = tunnel.info()?;
+//! println!("{} forwards to {}", info.public_url, info.config.addr);
+//! println!("{} connections so far", info.metrics.conns.count);
+//! # Ok::<(), ngrok::Error>(())
+//! ```
+
+use crate::Error;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+use url::{Position, Url};
+
+/// How long a request to the agent's API may take to connect, send or read, so that a
+/// wedged agent fails the call rather than hanging it past `.ready_timeout()`
+pub(crate) const API_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends requests to the agent's JSON API, e.g. through another HTTP client than `ureq`
+/// or a test double. Set with `Builder::api_transport()`; `UreqTransport` is the default,
+/// or `TcpTransport` without the `ureq` feature.
+///
+/// **Example**
+///
+/// ```
+/// use ngrok::api::{ApiResponse, ApiTransport};
+/// use ngrok::Error;
+///
+/// /// Answers as an agent without tunnels would
+/// #[derive(Debug)]
+/// struct NoTunnels;
+///
+/// impl ApiTransport for NoTunnels {
+///     fn send(
+///         &self,
+///         _method: &str,
+///         _url: &url::Url,
+///         _body: Option<&serde_json::Value>,
+///     ) -> Result<ApiResponse, Error> {
+///         Ok(ApiResponse {
+///             status: 200,
+///             body: r#"{"tunnels":[]}"#.to_string(),
+///         })
+///     }
+/// }
+///
+/// let builder = ngrok::builder().api_transport(NoTunnels);
+/// ```
+pub trait ApiTransport: fmt::Debug + Send + Sync {
+    /// Send the request, with a JSON body if given, and return the response. Only a
+    /// failure to get a response is an error; the agent's error responses are returned
+    /// like any other.
+    fn send(
+        &self,
+        method: &str,
+        url: &Url,
+        body: Option<&serde_json::Value>,
+    ) -> Result<ApiResponse, Error>;
+}
+
+/// A response of the agent's JSON API
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiResponse {
+    /// The HTTP status code, e.g. `201` for a created tunnel
+    pub status: u16,
+    /// The JSON body, or an empty string for responses without one
+    pub body: String,
+}
+
+/// Sends requests to the agent's JSON API with `ureq`
+#[cfg(feature = "ureq")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UreqTransport;
+
+#[cfg(feature = "ureq")]
+impl ApiTransport for UreqTransport {
+    fn send(
+        &self,
+        method: &str,
+        url: &Url,
+        body: Option<&serde_json::Value>,
+    ) -> Result<ApiResponse, Error> {
+        let mut request = ureq::request(method, url.as_str());
+        request.timeout(API_TIMEOUT);
+        let response = match body {
+            Some(body) => request.send_json(body.clone()),
+            None => request.call(),
+        };
+
+        if let Some(err) = response.synthetic_error() {
+            return Err(Error::Io(std::io::Error::other(err.to_string())));
+        }
+
+        Ok(ApiResponse {
+            status: response.status(),
+            body: response.into_string()?,
+        })
+    }
+}
+
+/// Sends requests to the agent's JSON API as HTTP/1.0 over a `TcpStream`, which is
+/// enough for its plain HTTP API on the local machine. It needs no HTTP client, e.g.
+/// for builds with only the `reqwest` feature. Like `UreqTransport`, it gives up on a
+/// request after a few seconds.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+impl ApiTransport for TcpTransport {
+    fn send(
+        &self,
+        method: &str,
+        url: &Url,
+        body: Option<&serde_json::Value>,
+    ) -> Result<ApiResponse, Error> {
+        let mut stream = connect(&url.socket_addrs(|| Some(80))?)?;
+        stream.set_read_timeout(Some(API_TIMEOUT))?;
+        stream.set_write_timeout(Some(API_TIMEOUT))?;
+
+        // Without keep-alive the agent closes the connection after the body, so it's
+        // neither chunked nor needs its length to be read
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let request = format!(
+            "{} {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            method,
+            &url[Position::BeforePath..Position::AfterQuery],
+            &url[Position::BeforeHost..Position::AfterPort],
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes())?;
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        parse_response(&response).ok_or(Error::MalformedAPIResponse)
+    }
+}
+
+/// Connect to the first of the addresses which accepts within `API_TIMEOUT`
+fn connect(addrs: &[SocketAddr]) -> std::io::Result<TcpStream> {
+    let mut last_err = std::io::Error::from(std::io::ErrorKind::AddrNotAvailable);
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, API_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// Parse a raw HTTP response, e.g. `HTTP/1.1 200 OK\r\n...\r\n\r\n{"tunnels":[]}`
+fn parse_response(response: &str) -> Option<ApiResponse> {
+    let (head, body) = response.split_once("\r\n\r\n")?;
+    let status = head.split_whitespace().nth(1)?.parse().ok()?;
+
+    Some(ApiResponse {
+        status,
+        body: body.to_string(),
+    })
+}
+
+/// The JSON API of the agent at `web_addr`, reached through a transport
+#[derive(Debug, Clone)]
+pub(crate) struct AgentApi {
+    web_addr: SocketAddr,
+    /// The transport set with `Builder::api_transport()`, or `None` for the default one
+    transport: Option<Arc<dyn ApiTransport>>,
+}
+
+impl AgentApi {
+    /// The API at `web_addr`, reached through the default transport
+    pub(crate) fn new(web_addr: SocketAddr) -> Self {
+        AgentApi {
+            web_addr,
+            transport: None,
+        }
+    }
+
+    pub(crate) fn with_transport(web_addr: SocketAddr, transport: Arc<dyn ApiTransport>) -> Self {
+        AgentApi {
+            web_addr,
+            transport: Some(transport),
+        }
+    }
+
+    fn transport(&self) -> &dyn ApiTransport {
+        match &self.transport {
+            Some(transport) => transport.as_ref(),
+            #[cfg(feature = "ureq")]
+            None => &UreqTransport,
+            #[cfg(not(feature = "ureq"))]
+            None => &TcpTransport,
+        }
+    }
+
+    pub(crate) fn web_addr(&self) -> SocketAddr {
+        self.web_addr
+    }
+
+    pub(crate) fn get(&self, path: &str) -> Result<serde_json::Value, Error> {
+        self.send("GET", path, &[], None)
+    }
+
+    pub(crate) fn post(
+        &self,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> Result<serde_json::Value, Error> {
+        self.send("POST", path, &[], Some(body))
+    }
+
+    pub(crate) fn delete(&self, path: &str) -> Result<(), Error> {
+        self.send("DELETE", path, &[], None).map(drop)
+    }
+
+    /// Send a request to the path, turning the agent's error responses into errors with
+    /// the message it sends. Empty bodies, e.g. of a `DELETE`, are returned as `null`.
+    pub(crate) fn send(
+        &self,
+        method: &str,
+        path: &str,
+        query: &[(&str, &str)],
+        body: Option<&serde_json::Value>,
+    ) -> Result<serde_json::Value, Error> {
+        let url = self.url(path, query)?;
+        parse_json(self.transport().send(method, &url, body)?)
+    }
+
+    /// Like `send()` without a body, without blocking the async runtime. The default
+    /// transport is replaced by `reqwest`, while one set with `Builder::api_transport()`
+    /// is called on the blocking thread pool.
+    #[cfg(feature = "reqwest")]
+    pub(crate) async fn send_async(
+        &self,
+        method: &str,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<serde_json::Value, Error> {
+        let url = self.url(path, query)?;
+
+        let response = match &self.transport {
+            Some(transport) => {
+                let (transport, method) = (transport.clone(), method.to_string());
+                tokio::task::spawn_blocking(move || transport.send(&method, &url, None))
+                    .await
+                    .map_err(|err| Error::Io(std::io::Error::other(err)))??
+            }
+            None => {
+                let method = reqwest::Method::from_bytes(method.as_bytes())
+                    .map_err(|err| Error::Io(std::io::Error::other(err)))?;
+                let response = reqwest::Client::new()
+                    .request(method, url)
+                    .send()
+                    .await
+                    .map_err(|err| Error::Io(std::io::Error::other(err)))?;
+                ApiResponse {
+                    status: response.status().as_u16(),
+                    body: response
+                        .text()
+                        .await
+                        .map_err(|err| Error::Io(std::io::Error::other(err)))?,
+                }
+            }
+        };
+
+        parse_json(response)
+    }
+
+    /// The URL of the path, with the query percent-encoded
+    fn url(&self, path: &str, query: &[(&str, &str)]) -> Result<Url, Error> {
+        let mut url = Url::parse(&format!("http://{}{}", self.web_addr, path))
+            .map_err(|err| Error::Io(std::io::Error::other(err)))?;
+        if !query.is_empty() {
+            url.query_pairs_mut().extend_pairs(query);
+        }
+        Ok(url)
+    }
+
+    /// The agent's live tunnels
+    pub(crate) fn list_tunnels(&self) -> Result<Vec<TunnelInfo>, Error> {
+        tunnels(&self.get("/api/tunnels")?)
+    }
+
+    /// Like `list_tunnels()`, without blocking the async runtime
+    #[cfg(feature = "reqwest")]
+    pub(crate) async fn list_tunnels_async(&self) -> Result<Vec<TunnelInfo>, Error> {
+        tunnels(&self.send_async("GET", "/api/tunnels", &[]).await?)
+    }
+}
+
+/// The JSON body of a response, turning the agent's error responses into errors with
+/// the message it sends. Empty bodies, e.g. of a `DELETE`, are returned as `null`.
+fn parse_json(response: ApiResponse) -> Result<serde_json::Value, Error> {
+    if response.status >= 400 {
+        let message = serde_json::from_str::<serde_json::Value>(&response.body)
+            .ok()
+            .and_then(|body| body.get("msg")?.as_str().map(str::to_string))
+            .unwrap_or_else(|| format!("status {}", response.status));
+        return Err(Error::Api(message));
+    }
+
+    if response.body.trim().is_empty() {
+        return Ok(serde_json::Value::Null);
+    }
+    serde_json::from_str(&response.body).map_err(|_| Error::MalformedAPIResponse)
+}
+
+/// A tunnel as listed by the agent's API
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TunnelInfo {
+    /// The tunnel's name in the agent, e.g. `command_line` or a name from its config
+    pub name: String,
+    /// The protocol of the public endpoint, e.g. `https` or `tcp`
+    #[serde(default)]
+    pub proto: String,
+    /// The public URL, which is empty for labeled tunnels since their Cloud Edge owns
+    /// the endpoint
+    #[serde(default)]
+    pub public_url: String,
+    #[serde(default)]
+    pub config: TunnelConfig,
+    #[serde(default)]
+    pub metrics: TunnelMetrics,
+}
+
+/// Where a tunnel forwards to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TunnelConfig {
+    /// The local address, e.g. `http://localhost:3030`
+    #[serde(default)]
+    pub addr: String,
+    /// Whether the agent captures the tunnel's requests for inspection
+    #[serde(default)]
+    pub inspect: bool,
+}
+
+/// The traffic through a tunnel so far
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TunnelMetrics {
+    /// The connections to the tunnel
+    #[serde(default)]
+    pub conns: Metric,
+    /// The HTTP requests through the tunnel, unless it's a TCP or TLS tunnel
+    #[serde(default)]
+    pub http: Metric,
+}
+
+/// Counts, rates and the durations of percentiles in nanoseconds, as measured by the
+/// agent
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Metric {
+    /// How many there were in total
+    pub count: u64,
+    /// How many are open right now
+    pub gauge: u64,
+    /// The rate per second over the last minute
+    pub rate1: f64,
+    /// The rate per second over the last five minutes
+    pub rate5: f64,
+    /// The rate per second over the last fifteen minutes
+    pub rate15: f64,
+    /// The median duration in nanoseconds, e.g. of a connection or request
+    pub p50: f64,
+    /// The 90th percentile duration in nanoseconds
+    pub p90: f64,
+    /// The 95th percentile duration in nanoseconds
+    pub p95: f64,
+    /// The 99th percentile duration in nanoseconds
+    pub p99: f64,
+}
+
+/// The response of `GET /api/tunnels`
+#[derive(Deserialize)]
+struct TunnelList {
+    tunnels: Vec<TunnelInfo>,
+}
+
+/// The live tunnels of the agent at `web_addr`, e.g. to find one of several by name.
+/// Use `Agent::tunnels()` for an agent started by this crate.
+///
+/// **Example**
+///
+/// ```no_run
+/// let tunnels = ngrok::list_tunnels("127.0.0.1:4040".parse().unwrap())?;
+/// for tunnel in &tunnels {
+///     println!("{} ({}): {}", tunnel.name, tunnel.proto, tunnel.public_url);
+/// }
+/// # Ok::<(), ngrok::Error>(())
+/// ```
+pub fn list_tunnels(web_addr: SocketAddr) -> Result<Vec<TunnelInfo>, Error> {
+    AgentApi::new(web_addr).list_tunnels()
+}
+
+/// Like `list_tunnels()`, through `reqwest` without blocking the async runtime
+#[cfg(feature = "reqwest")]
+pub async fn list_tunnels_async(web_addr: SocketAddr) -> Result<Vec<TunnelInfo>, Error> {
+    AgentApi::new(web_addr).list_tunnels_async().await
+}
+
+/// The path of the named tunnel in the agent's API, with the name percent-encoded since
+/// names from configs may contain reserved characters
+pub(crate) fn tunnel_path(name: &str) -> String {
+    let mut url = Url::parse("http://localhost/api/tunnels").unwrap();
+    url.path_segments_mut().unwrap().push(name);
+    url.path().to_string()
+}
+
+/// The tunnels in a response of `GET /api/tunnels`
+pub(crate) fn tunnels(response: &serde_json::Value) -> Result<Vec<TunnelInfo>, Error> {
+    TunnelList::deserialize(response)
+        .map(|list| list.tunnels)
+        .map_err(|_| Error::MalformedAPIResponse)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    /// Answers each request with the next of its responses, recording the requests
+    #[derive(Debug)]
+    pub(crate) struct FakeTransport {
+        responses: Mutex<Vec<ApiResponse>>,
+        pub(crate) requests: Mutex<Vec<(String, String, Option<serde_json::Value>)>>,
+    }
+
+    impl FakeTransport {
+        /// A transport answering every request with `200 OK` and the given bodies
+        pub(crate) fn ok(bodies: &[serde_json::Value]) -> Arc<Self> {
+            let responses = bodies
+                .iter()
+                .map(|body| ApiResponse {
+                    status: 200,
+                    body: body.to_string(),
+                })
+                .collect();
+
+            Arc::new(FakeTransport {
+                responses: Mutex::new(responses),
+                requests: Default::default(),
+            })
+        }
+    }
+
+    impl ApiTransport for FakeTransport {
+        fn send(
+            &self,
+            method: &str,
+            url: &Url,
+            body: Option<&serde_json::Value>,
+        ) -> Result<ApiResponse, Error> {
+            let request = (method.to_string(), url.to_string(), body.cloned());
+            self.requests.lock().unwrap().push(request);
+            Ok(self.responses.lock().unwrap().remove(0))
+        }
+    }
+
+    #[test]
+    fn test_agent_api() {
+        let response = |status, body: &str| ApiResponse {
+            status,
+            body: body.to_string(),
+        };
+        let transport = Arc::new(FakeTransport {
+            responses: Mutex::new(vec![
+                response(
+                    200,
+                    r#"{"tunnels":[{"name":"web","public_url":"https://abc123.ngrok.io"}]}"#,
+                ),
+                response(201, r#"{"name":"db"}"#),
+                response(204, ""),
+                response(404, r#"{"error_code":100,"msg":"tunnel not found"}"#),
+                response(502, "bad gateway"),
+            ]),
+            requests: Default::default(),
+        });
+        let api = AgentApi::with_transport("127.0.0.1:4041".parse().unwrap(), transport.clone());
+
+        assert_eq!(api.list_tunnels().unwrap()[0].name, "web");
+        assert_eq!(
+            api.post("/api/tunnels", &json!({ "name": "db" })).unwrap(),
+            json!({ "name": "db" })
+        );
+        api.delete("/api/tunnels/db").unwrap();
+        assert!(matches!(
+            api.send("GET", "/api/requests/http", &[("tunnel_name", "a b")], None),
+            Err(Error::Api(message)) if message == "tunnel not found"
+        ));
+        assert!(
+            matches!(api.get("/api/tunnels"), Err(Error::Api(message)) if message == "status 502")
+        );
+
+        let requests = transport.requests.lock().unwrap();
+        assert_eq!(
+            requests[1],
+            (
+                "POST".to_string(),
+                "http://127.0.0.1:4041/api/tunnels".to_string(),
+                Some(json!({ "name": "db" }))
+            )
+        );
+        assert_eq!(requests[2].0, "DELETE");
+        assert_eq!(
+            requests[3].1,
+            "http://127.0.0.1:4041/api/requests/http?tunnel_name=a+b"
+        );
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_agent_api_async() {
+        let transport = FakeTransport::ok(&[json!({ "tunnels": [{ "name": "web" }] })]);
+        transport.responses.lock().unwrap().push(ApiResponse {
+            status: 404,
+            body: r#"{"error_code":100,"msg":"tunnel not found"}"#.to_string(),
+        });
+        let api = AgentApi::with_transport("127.0.0.1:4041".parse().unwrap(), transport.clone());
+
+        // The transport set on the builder is used, with the same error handling
+        assert_eq!(api.list_tunnels_async().await.unwrap()[0].name, "web");
+        assert!(matches!(
+            api.send_async("DELETE", "/api/requests/http", &[]).await,
+            Err(Error::Api(message)) if message == "tunnel not found"
+        ));
+        assert_eq!(transport.requests.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_tunnels() {
+        let response = json!({
+            "tunnels": [
+                {
+                    "name": "command_line",
+                    "ID": "2c6b6b9c6d3c",
+                    "uri": "/api/tunnels/command_line",
+                    "public_url": "https://abc123.ngrok.io",
+                    "proto": "https",
+                    "config": { "addr": "http://localhost:3030", "inspect": true },
+                    "metrics": {
+                        "conns": { "count": 12, "gauge": 2, "rate1": 0.5, "p50": 1500000.0 },
+                        "http": { "count": 30 }
+                    }
+                },
+                { "name": "edge", "public_url": "" }
+            ],
+            "uri": "/api/tunnels"
+        });
+
+        let tunnels = tunnels(&response).unwrap();
+        assert_eq!(tunnels[0].public_url, "https://abc123.ngrok.io");
+        assert_eq!(tunnels[0].proto, "https");
+        assert_eq!(tunnels[0].config.addr, "http://localhost:3030");
+        assert!(tunnels[0].config.inspect);
+        assert_eq!(tunnels[0].metrics.conns.count, 12);
+        assert_eq!(tunnels[0].metrics.conns.gauge, 2);
+        assert_eq!(tunnels[0].metrics.conns.p50, 1500000.0);
+        assert_eq!(tunnels[0].metrics.http.count, 30);
+        assert_eq!(tunnels[1].metrics, TunnelMetrics::default());
+
+        assert!(matches!(
+            super::tunnels(&json!({})),
+            Err(Error::MalformedAPIResponse)
+        ));
+        assert!(super::tunnels(&json!({ "tunnels": [{ "public_url": "" }] })).is_err());
+    }
+
+    #[test]
+    fn test_tcp_transport() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/api/tunnels?a=b",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            while !request.ends_with(b"}") {
+                let mut buf = [0; 1024];
+                let read = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..read]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 201 Created\r\nContent-Type: application/json\r\n\r\n{\"name\":\"db\"}")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let response = TcpTransport
+            .send("POST", &url, Some(&json!({ "name": "db" })))
+            .unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.body, r#"{"name":"db"}"#);
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /api/tunnels?a=b HTTP/1.0\r\n"));
+        assert!(request.contains(&format!(
+            "Host: {}\r\n",
+            &url[Position::BeforeHost..Position::AfterPort]
+        )));
+        assert!(request.ends_with("Content-Length: 13\r\n\r\n{\"name\":\"db\"}"));
+
+        assert!(parse_response("HTTP/1.1 200 OK\r\n").is_none());
+        assert_eq!(
+            parse_response("HTTP/1.0 204 No Content\r\n\r\n").map(|response| response.status),
+            Some(204)
+        );
+    }
+
+    #[test]
+    fn test_tcp_transport_timeout() {
+        // An agent which accepts the connection but never answers
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/api/tunnels",
+            listener.local_addr().unwrap()
+        ));
+
+        let started_at = std::time::Instant::now();
+        assert!(TcpTransport.send("GET", &url.unwrap(), None).is_err());
+        assert!(started_at.elapsed() >= API_TIMEOUT);
+        assert!(started_at.elapsed() < API_TIMEOUT * 2);
+    }
+
+    #[test]
+    fn test_tunnel_path() {
+        assert_eq!(tunnel_path("web"), "/api/tunnels/web");
+        assert_eq!(tunnel_path("web (http)"), "/api/tunnels/web%20(http)");
+        assert_eq!(tunnel_path("a/b?c#d"), "/api/tunnels/a%2Fb%3Fc%23d");
+    }
+}
@@ -0,0 +1,227 @@
+//! An HTTP client for requests through a tunnel, e.g. in integration tests.
+
+#[cfg(feature = "reqwest")]
+use crate::Error;
+use crate::PublicUrl;
+use url::Url;
+
+/// The header which skips the browser warning ngrok shows on free plans
+const SKIP_BROWSER_WARNING: &str = "ngrok-skip-browser-warning";
+
+/// A `ureq` agent for requests through a tunnel, returned by `Tunnel::http_client()`.
+/// Paths are relative to the tunnel's public URL, which follows it when the agent
+/// reconnects, and every request skips the browser warning ngrok shows on free plans
+/// instead of the response.
+///
+/// **Example**
+///
+/// ```no_run
+/// let tunnel = ngrok::builder().port(3030).run()?;
+/// let client = tunnel.http_client();
+///
+/// let response = client.get("/health").call();
+/// assert_eq!(response.status(), 200);
+/// # Ok::<(), ngrok::Error>(())
+/// ```
+#[cfg(feature = "ureq")]
+#[derive(Debug, Clone)]
+pub struct TunnelClient {
+    agent: ureq::Agent,
+    public_url: PublicUrl,
+}
+
+#[cfg(feature = "ureq")]
+impl TunnelClient {
+    pub(crate) fn new(public_url: PublicUrl) -> Self {
+        let mut agent = ureq::agent();
+        agent.set(SKIP_BROWSER_WARNING, "true");
+        TunnelClient { agent, public_url }
+    }
+
+    /// The URL of the path through the tunnel, e.g. `https://abc123.ngrok.io/health` for
+    /// `/health`
+    pub fn url(&self, path: &str) -> Result<Url, url::ParseError> {
+        self.public_url.get().join(path)
+    }
+
+    /// A request with the given method to the path through the tunnel. Sending it fails
+    /// if the path isn't a valid URL relative to the public URL.
+    pub fn request(&self, method: &str, path: &str) -> ureq::Request {
+        match self.url(path) {
+            Ok(url) => self.agent.request(method, url.as_str()),
+            Err(_) => self.agent.request(method, path),
+        }
+    }
+
+    /// A `GET` request to the path through the tunnel
+    pub fn get(&self, path: &str) -> ureq::Request {
+        self.request("GET", path)
+    }
+
+    /// A `POST` request to the path through the tunnel
+    pub fn post(&self, path: &str) -> ureq::Request {
+        self.request("POST", path)
+    }
+
+    /// A `PUT` request to the path through the tunnel
+    pub fn put(&self, path: &str) -> ureq::Request {
+        self.request("PUT", path)
+    }
+
+    /// A `DELETE` request to the path through the tunnel
+    pub fn delete(&self, path: &str) -> ureq::Request {
+        self.request("DELETE", path)
+    }
+
+    /// The underlying `ureq` agent, e.g. to set more headers
+    pub fn agent(&mut self) -> &mut ureq::Agent {
+        &mut self.agent
+    }
+}
+
+/// Like `TunnelClient`, for `reqwest`, returned by `Tunnel::reqwest_client()`
+///
+/// **Example**
+///
+/// ```no_run
+/// # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+/// # let tunnel = ngrok::builder().port(3030).run()?;
+/// let client = tunnel.reqwest_client()?;
+///
+/// let response = client.get("/health").send().await?;
+/// assert_eq!(response.status(), 200);
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "reqwest")]
+#[derive(Debug, Clone)]
+pub struct ReqwestClient {
+    client: reqwest::Client,
+    public_url: PublicUrl,
+}
+
+#[cfg(feature = "reqwest")]
+impl ReqwestClient {
+    /// Fails if `reqwest` can't set up its TLS backend
+    pub(crate) fn new(public_url: PublicUrl) -> Result<Self, Error> {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            SKIP_BROWSER_WARNING,
+            reqwest::header::HeaderValue::from_static("true"),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|err| Error::Io(std::io::Error::other(err)))?;
+        Ok(ReqwestClient { client, public_url })
+    }
+
+    /// The URL of the path through the tunnel, like `TunnelClient::url()`
+    pub fn url(&self, path: &str) -> Result<Url, url::ParseError> {
+        self.public_url.get().join(path)
+    }
+
+    /// A request with the given method to the path through the tunnel. Sending it fails
+    /// if the path isn't a valid URL relative to the public URL.
+    pub fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        match self.url(path) {
+            Ok(url) => self.client.request(method, url),
+            Err(_) => self.client.request(method, path),
+        }
+    }
+
+    /// A `GET` request to the path through the tunnel
+    pub fn get(&self, path: &str) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::GET, path)
+    }
+
+    /// A `POST` request to the path through the tunnel
+    pub fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::POST, path)
+    }
+
+    /// A `PUT` request to the path through the tunnel
+    pub fn put(&self, path: &str) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::PUT, path)
+    }
+
+    /// A `DELETE` request to the path through the tunnel
+    pub fn delete(&self, path: &str) -> reqwest::RequestBuilder {
+        self.request(reqwest::Method::DELETE, path)
+    }
+
+    /// The underlying `reqwest` client
+    pub fn client(&self) -> &reqwest::Client {
+        &self.client
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::AgentApi;
+    use crate::{Lookup, DEFAULT_WEB_ADDR};
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answer one request with no content, returning its lowercased head
+    fn serve_once(listener: TcpListener) -> thread::JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            for line in BufReader::new(&stream).lines() {
+                let line = line.unwrap();
+                if line.is_empty() {
+                    break;
+                }
+                request.push(line.to_lowercase());
+            }
+            (&stream)
+                .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            request
+        })
+    }
+
+    #[cfg(feature = "ureq")]
+    #[test]
+    fn test_http_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = Url::parse(&format!("http://{}/app/", listener.local_addr().unwrap())).unwrap();
+        let client = TunnelClient::new(PublicUrl::new(
+            base.clone(),
+            AgentApi::new(DEFAULT_WEB_ADDR),
+            Lookup::Name("web".to_string()),
+        ));
+        assert_eq!(client.url("health").unwrap(), base.join("health").unwrap());
+        assert!(client.url("//[").is_err());
+        assert!(client.get("//[").call().synthetic());
+
+        let server = serve_once(listener);
+        assert_eq!(client.get("health").call().status(), 204);
+        let request = server.join().unwrap();
+        assert_eq!(request[0], "get /app/health http/1.1");
+        assert!(request.contains(&"ngrok-skip-browser-warning: true".to_string()));
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn test_reqwest_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let client = ReqwestClient::new(PublicUrl::new(
+            base,
+            AgentApi::new(DEFAULT_WEB_ADDR),
+            Lookup::Name("web".to_string()),
+        ))
+        .unwrap();
+
+        let server = serve_once(listener);
+        let response = client.post("/hooks").send().await.unwrap();
+        assert_eq!(response.status(), 204);
+        let request = server.join().unwrap();
+        assert_eq!(request[0], "post /hooks http/1.1");
+        assert!(request.contains(&"ngrok-skip-browser-warning: true".to_string()));
+    }
+}
@@ -0,0 +1,219 @@
+//! Strategies for finding the public URL of a started tunnel. `LogParsing` is the
+//! default; another strategy is selected with `Builder::discovery()`.
+//!
+//! **Example**
+//!
+//! ```no_run
+//! use ngrok::discovery::{ApiPolling, Custom, TunnelQuery};
+//!
+//! // Find the tunnel in the agent's web interface instead of its log
+//! let tunnel = ngrok::builder().discovery(ApiPolling).port(3030).run()?;
+//!
+//! // Or in any other way, e.g. when the public URL is known up front
+//! let tunnel = ngrok::builder()
+//!     .discovery(Custom(|_: &TunnelQuery| Ok(url::Url::parse("https://dev.example.com").unwrap())))
+//!     .port(3030)
+//!     .run()?;
+//! # Ok::<(), ngrok::Error>(())
+//! ```
+
+use crate::api::{AgentApi, TunnelInfo};
+use crate::{Error, Protocol, Scheme};
+use std::fmt;
+use std::net::SocketAddr;
+use url::Url;
+
+/// Finds the public URL of a started tunnel. Both methods are called until one of
+/// them returns the URL or the builder's ready timeout elapses.
+pub trait Discovery: fmt::Debug + Send + Sync {
+    /// Look for the tunnel in a line of the agent's JSON log on stdout
+    fn log_line(&self, query: &TunnelQuery, line: &str) -> Option<Result<Url, Error>> {
+        let _ = (query, line);
+        None
+    }
+
+    /// Look for the tunnel after each of the builder's backoff delays. The error of
+    /// the last attempt is returned from `run()` if the tunnel is never found.
+    fn poll(&self, query: &TunnelQuery) -> Result<Url, Error> {
+        let _ = query;
+        Err(Error::TunnelNotFound)
+    }
+}
+
+/// Find the tunnel in the "started tunnel" event of the agent's JSON log
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogParsing;
+
+impl Discovery for LogParsing {
+    fn log_line(&self, query: &TunnelQuery, line: &str) -> Option<Result<Url, Error>> {
+        query.find_in_log(line)
+    }
+}
+
+/// Find the tunnel among those listed by the API of the agent's web interface,
+/// at `Builder::web_addr()` or the free port picked for the agent
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApiPolling;
+
+impl Discovery for ApiPolling {
+    fn poll(&self, query: &TunnelQuery) -> Result<Url, Error> {
+        query.find(&query.api.list_tunnels()?)
+    }
+}
+
+/// Find the tunnel with a function, which is polled like `Discovery::poll()`
+pub struct Custom<F>(pub F);
+
+impl<F> fmt::Debug for Custom<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Custom")
+    }
+}
+
+impl<F> Discovery for Custom<F>
+where
+    F: Fn(&TunnelQuery) -> Result<Url, Error> + Send + Sync,
+{
+    fn poll(&self, query: &TunnelQuery) -> Result<Url, Error> {
+        (self.0)(query)
+    }
+}
+
+/// Identifies the started tunnel among those reported by the agent
+#[derive(Debug, Clone)]
+pub struct TunnelQuery {
+    pub(crate) proto: Protocol,
+    pub(crate) scheme: Scheme,
+    pub(crate) port: u16,
+    /// The port or socket path, as found in the local address the agent reports
+    pub(crate) fragment: String,
+    pub(crate) domain: Option<String>,
+    pub(crate) labeled: bool,
+    pub(crate) api: AgentApi,
+}
+
+impl TunnelQuery {
+    /// The scheme of the tunnel's public URL, e.g. `https://`
+    pub fn scheme(&self) -> &'static str {
+        self.proto.url_scheme(self.scheme)
+    }
+
+    /// The local port the tunnel forwards to, or 0 for a Unix socket
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The custom domain of the tunnel, if one was set
+    pub fn domain(&self) -> Option<&str> {
+        self.domain.as_deref()
+    }
+
+    /// Whether the tunnel is labeled, i.e. attached to a Cloud Edge
+    pub fn is_labeled(&self) -> bool {
+        self.labeled
+    }
+
+    /// The address of the agent's web interface and API
+    pub fn web_addr(&self) -> SocketAddr {
+        self.api.web_addr()
+    }
+
+    /// Check whether a line of the agent's JSON log reports the started tunnel,
+    /// e.g. `{"msg":"started tunnel","addr":"http://localhost:3030","url":"https://..."}`
+    pub(crate) fn find_in_log(&self, line: &str) -> Option<Result<Url, Error>> {
+        let event: serde_json::Value = serde_json::from_str(line).ok()?;
+
+        if event.get("msg").and_then(|msg| msg.as_str()) != Some("started tunnel") {
+            return None;
+        }
+
+        self.matches(
+            event.get("url").and_then(|url| url.as_str()),
+            event.get("addr").and_then(|addr| addr.as_str()),
+        )
+    }
+
+    /// Find the started tunnel among those listed by the agent API
+    pub(crate) fn find(&self, tunnels: &[TunnelInfo]) -> Result<Url, Error> {
+        for tunnel in tunnels {
+            let matched = self.matches(Some(&tunnel.public_url), Some(&tunnel.config.addr));
+            if let Some(public_url) = matched {
+                return public_url;
+            }
+        }
+
+        Err(Error::TunnelNotFound)
+    }
+
+    /// The public URLs of the started tunnel among those listed by the agent API, with
+    /// an HTTP URL as well as the HTTPS one if the agent opened both. Pooled tunnels
+    /// share a URL, which is only listed once.
+    pub(crate) fn find_all(&self, tunnels: &[TunnelInfo]) -> Vec<Url> {
+        // Labeled tunnels are only reachable at the edge's hostname
+        let schemes: &[&str] = match self.proto {
+            Protocol::Http if !self.labeled => &["https://", "http://"],
+            _ => &[self.scheme()],
+        };
+
+        tunnels
+            .iter()
+            .flat_map(|tunnel| {
+                schemes.iter().filter_map(move |scheme| {
+                    self.matches_scheme(scheme, Some(&tunnel.public_url), Some(&tunnel.config.addr))
+                })
+            })
+            .filter_map(Result::ok)
+            .fold(Vec::new(), |mut urls, url| {
+                if !urls.contains(&url) {
+                    urls.push(url);
+                }
+                urls
+            })
+    }
+
+    /// The public URL if a tunnel with this URL and local address is the started one
+    fn matches(&self, tunnel_url: Option<&str>, addr: Option<&str>) -> Option<Result<Url, Error>> {
+        self.matches_scheme(self.scheme(), tunnel_url, addr)
+    }
+
+    fn matches_scheme(
+        &self,
+        scheme: &str,
+        tunnel_url: Option<&str>,
+        addr: Option<&str>,
+    ) -> Option<Result<Url, Error>> {
+        let is_port = addr
+            .map(|addr| addr.contains(&self.fragment))
+            .unwrap_or(false);
+
+        // Labeled tunnels don't report a public URL since the Cloud Edge owns the
+        // endpoint, so the edge's hostname stands in for it once the tunnel is up
+        if self.labeled {
+            if !is_port {
+                return None;
+            }
+            let domain = self.domain.as_deref().unwrap_or_default();
+            return Some(
+                Url::parse(&format!("{}{}", self.scheme(), domain))
+                    .map_err(|_| Error::MalformedAPIResponse),
+            );
+        }
+
+        // snag the URL matching the requested protocol, and hostname when a custom domain is used
+        let is_scheme = tunnel_url.map(|url| url.contains(scheme)).unwrap_or(false);
+
+        let is_domain = match &self.domain {
+            Some(domain) => tunnel_url
+                .and_then(|url| Url::parse(url).ok())
+                .map(|url| url.host_str() == Some(domain))
+                .unwrap_or(false),
+            None => true,
+        };
+
+        if is_scheme && is_port && is_domain {
+            return Some(Url::parse(tunnel_url.unwrap()).map_err(|_| Error::MalformedAPIResponse));
+        }
+
+        None
+    }
+}
@@ -0,0 +1,351 @@
+//! Download the `ngrok` agent for the current platform, e.g. on CI images which don't
+//! have it installed. Requires the `download` feature.
+//!
+//! **Example**
+//!
+//! ```no_run
+//! use ngrok::download::Download;
+//!
+//! // The latest stable agent
+//! let ngrok = ngrok::ensure_installed()?;
+//!
+//! // Or an audited release, which is checked before it's extracted
+//! let ngrok = Download::new()
+//!     .version("3.5.0")
+//!     .url("https://example.com/ngrok-v3-3.5.0-linux-amd64.tgz")
+//!     .sha256("4e8f2c...")
+//!     .ensure_installed()?;
+//!
+//! let tunnel = ngrok::builder().executable(ngrok).port(3030).run()?;
+//! # Ok::<(), ngrok::Error>(())
+//! ```
+
+use crate::Error;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Where the stable agents are published, named by platform
+const DOWNLOADS: &str = "https://bin.equinox.io/c/bNyj1mQVY4c";
+
+/// The name of the agent's executable in its archive
+const EXECUTABLE: &str = if cfg!(windows) { "ngrok.exe" } else { "ngrok" };
+
+/// The path of the latest stable v3 agent for this platform, which is downloaded into
+/// the user's cache directory unless it's already there. Pass it to
+/// `Builder::executable()`.
+pub fn ensure_installed() -> Result<PathBuf, Error> {
+    Download::new().ensure_installed()
+}
+
+/// Which agent to download. By default the latest stable v3 agent for this platform.
+#[derive(Debug, Clone, Default)]
+pub struct Download {
+    version: Option<String>,
+    url: Option<String>,
+    sha256: Option<String>,
+}
+
+impl Download {
+    /// Download the latest stable v3 agent
+    pub fn new() -> Self {
+        Download {
+            ..Default::default()
+        }
+    }
+
+    /// Pin the agent to a release, e.g. `3.5.0`. It's cached apart from other releases
+    /// and the downloaded agent must report this version. Only the latest release is
+    /// published under a predictable URL, so set `.url()` to its archive as well.
+    pub fn version(mut self, version: &str) -> Self {
+        self.version = Some(version.to_string());
+        self
+    }
+
+    /// Download the archive from this URL, a `.tgz` or `.zip` as published by `ngrok`
+    pub fn url(mut self, url: &str) -> Self {
+        self.url = Some(url.to_string());
+        self
+    }
+
+    /// Verify the archive against its published SHA-256 checksum, in hex, before
+    /// extracting it
+    pub fn sha256(mut self, checksum: &str) -> Self {
+        self.sha256 = Some(checksum.to_ascii_lowercase());
+        self
+    }
+
+    /// The path of the agent, which is downloaded into the user's cache directory unless
+    /// it's already there. Pass it to `Builder::executable()`.
+    pub fn ensure_installed(&self) -> Result<PathBuf, Error> {
+        let dir = cache_dir()?.join("ngrok-rs").join(self.cache_key());
+        let path = dir.join(EXECUTABLE);
+
+        if path.exists() {
+            return Ok(path);
+        }
+
+        let url = match &self.url {
+            Some(url) => url.clone(),
+            None => archive_url(std::env::consts::OS, std::env::consts::ARCH)?,
+        };
+        let archive = fetch(&url)?;
+
+        if let Some(expected) = &self.sha256 {
+            verify(&archive, expected)?;
+        }
+
+        fs::create_dir_all(&dir)?;
+        install(
+            &archive,
+            url.ends_with(".zip"),
+            &path,
+            self.version.as_deref(),
+        )?;
+
+        Ok(path)
+    }
+
+    /// The name of the cache directory, which differs by release and checksum so that
+    /// a stale agent never satisfies a new pin
+    fn cache_key(&self) -> String {
+        let mut key = match &self.version {
+            Some(version) => format!("v{}", version),
+            None => "v3-stable".to_string(),
+        };
+
+        if let Some(checksum) = &self.sha256 {
+            key.push('-');
+            key.extend(checksum.chars().take(16));
+        }
+
+        key
+    }
+}
+
+/// The URL of the agent archive for an OS and architecture, as named by `std::env::consts`
+fn archive_url(os: &str, arch: &str) -> Result<String, Error> {
+    let unsupported = |name| Error::Download(format!("no agent is published for {}", name));
+
+    let os = match os {
+        "linux" | "freebsd" | "windows" => os,
+        "macos" => "darwin",
+        _ => return Err(unsupported(os)),
+    };
+
+    let arch = match arch {
+        "x86_64" => "amd64",
+        "x86" => "386",
+        "aarch64" => "arm64",
+        "arm" => "arm",
+        _ => return Err(unsupported(arch)),
+    };
+
+    let extension = match os {
+        "linux" | "freebsd" => "tgz",
+        _ => "zip",
+    };
+
+    Ok(format!(
+        "{}/ngrok-v3-stable-{}-{}.{}",
+        DOWNLOADS, os, arch, extension
+    ))
+}
+
+/// The directory for per-user caches on this platform
+fn cache_dir() -> Result<PathBuf, Error> {
+    let var = |name| std::env::var_os(name).map(PathBuf::from);
+
+    let dir = if cfg!(windows) {
+        var("LOCALAPPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library/Caches"))
+    } else {
+        var("XDG_CACHE_HOME").or_else(|| var("HOME").map(|home| home.join(".cache")))
+    };
+
+    dir.ok_or_else(|| Error::Download("no cache directory to download the agent to".to_string()))
+}
+
+fn fetch(url: &str) -> Result<Vec<u8>, Error> {
+    let response = ureq::get(url).call();
+
+    if let Some(err) = response.synthetic_error() {
+        return Err(Error::Download(format!("{}: {}", url, err)));
+    }
+    if response.error() {
+        return Err(Error::Download(format!(
+            "{}: {}",
+            url,
+            response.status_line()
+        )));
+    }
+
+    let mut archive = Vec::new();
+    response.into_reader().read_to_end(&mut archive)?;
+    Ok(archive)
+}
+
+/// Fail unless the archive has the expected SHA-256 checksum
+fn verify(archive: &[u8], expected: &str) -> Result<(), Error> {
+    let checksum: String = Sha256::digest(archive)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    if checksum != expected {
+        return Err(Error::Download(format!(
+            "the archive's SHA-256 checksum is {}, expected {}",
+            checksum, expected
+        )));
+    }
+
+    Ok(())
+}
+
+/// Extract the executable from the archive to `path`, checking its version if pinned.
+/// It's written next to `path` and then moved there, so that concurrent installs never
+/// run a partially written agent.
+fn install(archive: &[u8], zip: bool, path: &Path, version: Option<&str>) -> Result<(), Error> {
+    static INSTALLS: AtomicUsize = AtomicUsize::new(0);
+
+    let partial = path.with_extension(format!(
+        "{}-{}.partial",
+        std::process::id(),
+        INSTALLS.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = extract(archive, zip, &partial)
+        .and_then(|()| match version {
+            Some(version) => check_version(&partial, version),
+            None => Ok(()),
+        })
+        .and_then(|()| Ok(fs::rename(&partial, path)?));
+    if result.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+
+    result
+}
+
+fn extract(archive: &[u8], zip: bool, path: &Path) -> Result<(), Error> {
+    let mut file = fs::File::create(path)?;
+
+    if zip {
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).map_err(malformed)?;
+        let mut executable = archive.by_name(EXECUTABLE).map_err(malformed)?;
+        io::copy(&mut executable, &mut file)?;
+    } else {
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+        let mut executable = archive
+            .entries()?
+            .filter_map(Result::ok)
+            .find(|entry| {
+                let path = entry.path().ok();
+                path.as_deref() == Some(Path::new(EXECUTABLE))
+            })
+            .ok_or_else(|| malformed("the archive has no agent"))?;
+        io::copy(&mut executable, &mut file)?;
+    }
+
+    file.flush()?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o755))?;
+    }
+
+    Ok(())
+}
+
+/// Fail unless the agent reports the pinned version, e.g. `ngrok version 3.5.0`
+fn check_version(executable: &Path, version: &str) -> Result<(), Error> {
+    let output = Command::new(executable).arg("version").output()?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let found = output.split_whitespace().last().unwrap_or_default();
+
+    if found != version {
+        return Err(Error::Download(format!(
+            "the archive has `ngrok` {}, expected {}",
+            found, version
+        )));
+    }
+
+    Ok(())
+}
+
+fn malformed(err: impl std::fmt::Display) -> Error {
+    Error::Download(format!("malformed agent archive: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_url() {
+        assert_eq!(
+            archive_url("linux", "x86_64").unwrap(),
+            "https://bin.equinox.io/c/bNyj1mQVY4c/ngrok-v3-stable-linux-amd64.tgz"
+        );
+        assert_eq!(
+            archive_url("macos", "aarch64").unwrap(),
+            "https://bin.equinox.io/c/bNyj1mQVY4c/ngrok-v3-stable-darwin-arm64.zip"
+        );
+        assert_eq!(
+            archive_url("windows", "x86").unwrap(),
+            "https://bin.equinox.io/c/bNyj1mQVY4c/ngrok-v3-stable-windows-386.zip"
+        );
+        assert!(archive_url("linux", "riscv64").is_err());
+    }
+
+    #[test]
+    fn test_extract_tgz() {
+        let mut tar = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        tar.append_data(&mut header, EXECUTABLE, &b"\x7fELF"[..])
+            .unwrap();
+
+        let mut archive = flate2::write::GzEncoder::new(Vec::new(), Default::default());
+        archive.write_all(&tar.into_inner().unwrap()).unwrap();
+        let archive = archive.finish().unwrap();
+
+        let dir = std::env::temp_dir().join(format!("ngrok-rs-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(EXECUTABLE);
+
+        install(&archive, false, &path, None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"\x7fELF");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_verify() {
+        let checksum = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert!(verify(b"hello", checksum).is_ok());
+        assert!(matches!(
+            verify(b"hello!", checksum),
+            Err(Error::Download(_))
+        ));
+    }
+
+    #[test]
+    fn test_cache_key() {
+        assert_eq!(Download::new().cache_key(), "v3-stable");
+        assert_eq!(
+            Download::new()
+                .version("3.5.0")
+                .sha256("2CF24DBA5FB0A30E26E83B2AC5B9E29E")
+                .cache_key(),
+            "v3.5.0-2cf24dba5fb0a30e"
+        );
+    }
+}
@@ -0,0 +1,453 @@
+//! Lifecycle events of an agent, parsed from its JSON log. Subscribe to them with
+//! `Tunnel::events()`.
+//!
+//! **Example**
+//!
+//! ```no_run
+//! use ngrok::events::AgentEvent;
+//!
+//! let tunnel = ngrok::builder().port(3030).run()?;
+//!
+//! for event in tunnel.events() {
+//!     if let AgentEvent::Reconnecting { reason } = event {
+//!         eprintln!("lost the session: {}", reason);
+//!     }
+//! }
+//! # Ok::<(), ngrok::Error>(())
+//! ```
+//!
+//! With the `async` feature, `Tunnel::event_stream()` delivers them as a `Stream`.
+
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use url::Url;
+
+/// An event in the agent's log
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AgentEvent {
+    /// The agent established its session with the `ngrok` service, after starting or
+    /// reconnecting
+    SessionEstablished,
+    /// A tunnel came online at its public URL
+    TunnelStarted { name: String, url: Url },
+    /// The session was lost and the agent is reconnecting, for the given reason
+    Reconnecting { reason: String },
+    /// The `ngrok` service is rate limiting the agent
+    RateLimited { message: String },
+    /// Any other error the agent logged
+    Error { message: String },
+}
+
+impl AgentEvent {
+    /// Parse a line of the agent's JSON log, e.g.
+    /// `{"lvl":"info","msg":"client session established","obj":"tunnels.session"}`
+    pub(crate) fn parse(line: &str) -> Option<Self> {
+        let event: serde_json::Value = serde_json::from_str(line).ok()?;
+        let field = |name| event.get(name).and_then(|value| value.as_str());
+
+        let msg = field("msg")?;
+        let err = field("err").unwrap_or(msg);
+
+        if err.contains("rate limit") || err.contains("too many") {
+            return Some(AgentEvent::RateLimited {
+                message: err.to_string(),
+            });
+        }
+
+        if msg.contains("session established") {
+            return Some(AgentEvent::SessionEstablished);
+        }
+
+        if msg == "started tunnel" {
+            return Some(AgentEvent::TunnelStarted {
+                name: field("name").unwrap_or_default().to_string(),
+                url: Url::parse(field("url")?).ok()?,
+            });
+        }
+
+        if msg == "session closing" || msg.contains("reconnect") {
+            return Some(AgentEvent::Reconnecting {
+                reason: err.to_string(),
+            });
+        }
+
+        match field("lvl") {
+            Some("eror" | "crit") => Some(AgentEvent::Error {
+                message: err.to_string(),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl AgentEvent {
+    /// Report the event to `tracing` subscribers
+    #[cfg(feature = "tracing")]
+    pub(crate) fn trace(&self) {
+        match self {
+            AgentEvent::SessionEstablished => tracing::info!("the agent established its session"),
+            AgentEvent::TunnelStarted { name, url } => {
+                tracing::info!(name, %url, "the agent started the tunnel")
+            }
+            AgentEvent::Reconnecting { reason } => {
+                tracing::warn!(reason, "the agent lost its session and is reconnecting")
+            }
+            AgentEvent::RateLimited { message } => {
+                tracing::warn!(message, "the agent is rate limited")
+            }
+            AgentEvent::Error { message } => tracing::error!(message, "the agent logged an error"),
+        }
+    }
+}
+
+/// A `Stream` of an agent's events, from `Tunnel::event_stream()`. It ends once the
+/// agent exits.
+#[cfg(feature = "async")]
+#[derive(Debug)]
+pub struct EventStream(tokio::sync::mpsc::UnboundedReceiver<AgentEvent>);
+
+#[cfg(feature = "async")]
+impl futures_core::Stream for EventStream {
+    type Item = AgentEvent;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<AgentEvent>> {
+        self.0.poll_recv(cx)
+    }
+}
+
+/// The receivers of an agent's events. Events are only sent to receivers which
+/// subscribed before they were logged, and the receivers are disconnected once the
+/// log is closed, unless they're held for a restarted agent to take the log over.
+/// Tunnels without a log of their own have no events.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Subscribers {
+    receivers: Arc<Mutex<Receivers>>,
+    /// The log these handles publish, see `restarted()`
+    log: usize,
+}
+
+#[derive(Debug, Default)]
+struct Receivers {
+    /// `None` once the receivers are disconnected
+    subscribers: Option<Vec<Subscriber>>,
+    /// The log which is publishing, counting up as restarted agents take over
+    log: usize,
+    /// Whether that log is closed
+    closed: bool,
+    /// How many restarts the receivers are kept connected for, see `hold()`
+    holds: usize,
+}
+
+#[derive(Debug)]
+enum Subscriber {
+    Blocking(Sender<AgentEvent>),
+    #[cfg(feature = "async")]
+    Async(tokio::sync::mpsc::UnboundedSender<AgentEvent>),
+}
+
+impl Subscriber {
+    /// Send the event, unless the receiver is gone
+    fn send(&self, event: AgentEvent) -> bool {
+        match self {
+            Subscriber::Blocking(sender) => sender.send(event).is_ok(),
+            #[cfg(feature = "async")]
+            Subscriber::Async(sender) => sender.send(event).is_ok(),
+        }
+    }
+}
+
+impl Subscribers {
+    /// The subscribers of a log which is being watched
+    pub(crate) fn open() -> Self {
+        Subscribers {
+            receivers: Arc::new(Mutex::new(Receivers {
+                subscribers: Some(Vec::new()),
+                ..Default::default()
+            })),
+            log: 0,
+        }
+    }
+
+    /// Handles for the log of a restarted agent, which takes over from the previous
+    /// one: the events of the previous log are no longer published, nor does it
+    /// disconnect the subscribers once it's closed
+    pub(crate) fn restarted(&self) -> Subscribers {
+        let mut receivers = self.receivers.lock().unwrap();
+        receivers.log += 1;
+        receivers.closed = false;
+
+        Subscribers {
+            receivers: self.receivers.clone(),
+            log: receivers.log,
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<AgentEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.add(Subscriber::Blocking(sender));
+        receiver
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn stream(&self) -> EventStream {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        self.add(Subscriber::Async(sender));
+        EventStream(receiver)
+    }
+
+    fn add(&self, subscriber: Subscriber) {
+        if let Some(subscribers) = self.receivers.lock().unwrap().subscribers.as_mut() {
+            subscribers.push(subscriber);
+        }
+    }
+
+    /// Send an event to the subscribers which are still receiving
+    pub(crate) fn publish(&self, event: AgentEvent) {
+        let mut receivers = self.receivers.lock().unwrap();
+        if receivers.log != self.log {
+            return;
+        }
+        if let Some(subscribers) = receivers.subscribers.as_mut() {
+            subscribers.retain(|subscriber| subscriber.send(event.clone()));
+        }
+    }
+
+    /// Disconnect the subscribers once the log is closed
+    pub(crate) fn close(&self) {
+        let mut receivers = self.receivers.lock().unwrap();
+        if receivers.log == self.log {
+            receivers.closed = true;
+            if receivers.holds == 0 {
+                receivers.subscribers.take();
+            }
+        }
+    }
+
+    /// Keep the subscribers connected while the agent is restarted, even if its log is
+    /// closed in the meantime, until `release()`
+    pub(crate) fn hold(&self) {
+        self.receivers.lock().unwrap().holds += 1;
+    }
+
+    /// Undo a `hold()`, disconnecting the subscribers if the log is closed and they
+    /// aren't held otherwise
+    pub(crate) fn release(&self) {
+        let mut receivers = self.receivers.lock().unwrap();
+        receivers.holds -= 1;
+        if receivers.holds == 0 && receivers.closed {
+            receivers.subscribers.take();
+        }
+    }
+}
+
+type DisconnectHook = Arc<dyn Fn(&str) + Send + Sync>;
+type ReconnectHook = Arc<dyn Fn() + Send + Sync>;
+
+/// The `Builder::on_disconnect()` and `Builder::on_reconnect()` callbacks
+#[derive(Clone, Default)]
+pub(crate) struct SessionHooks {
+    pub(crate) on_disconnect: Option<DisconnectHook>,
+    pub(crate) on_reconnect: Option<ReconnectHook>,
+}
+
+impl fmt::Debug for SessionHooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionHooks")
+            .field("on_disconnect", &self.on_disconnect.is_some())
+            .field("on_reconnect", &self.on_reconnect.is_some())
+            .finish()
+    }
+}
+
+impl SessionHooks {
+    /// Whether `on_disconnect()` or `on_reconnect()` was called
+    pub(crate) fn is_set(&self) -> bool {
+        self.on_disconnect.is_some() || self.on_reconnect.is_some()
+    }
+
+    /// Call the hooks from a watcher thread as the agent's session is lost and
+    /// re-established, until the agent exits. Called before the agent is spawned, so
+    /// that a session lost while the tunnel starts is reported too.
+    pub(crate) fn watch(&self, events: &Subscribers) {
+        if !self.is_set() {
+            return;
+        }
+
+        let hooks = self.clone();
+        let events = events.subscribe();
+
+        thread::spawn(move || {
+            let mut connected = true;
+            for event in events {
+                hooks.handle(&mut connected, &event);
+            }
+        });
+    }
+
+    /// Call the hook for the event if it changes whether the session is up. The agent
+    /// logs several events while reconnecting, which are reported once.
+    fn handle(&self, connected: &mut bool, event: &AgentEvent) {
+        match event {
+            AgentEvent::Reconnecting { reason } if *connected => {
+                *connected = false;
+                if let Some(on_disconnect) = &self.on_disconnect {
+                    on_disconnect(reason);
+                }
+            }
+            AgentEvent::SessionEstablished if !*connected => {
+                *connected = true;
+                if let Some(on_reconnect) = &self.on_reconnect {
+                    on_reconnect();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_events() {
+        let parse = AgentEvent::parse;
+
+        assert_eq!(
+            parse(r#"{"lvl":"info","msg":"client session established","obj":"tunnels.session"}"#),
+            Some(AgentEvent::SessionEstablished)
+        );
+        assert_eq!(
+            parse(
+                r#"{"lvl":"info","msg":"started tunnel","name":"command_line","addr":"http://localhost:3030","url":"https://abc123.ngrok.io"}"#
+            ),
+            Some(AgentEvent::TunnelStarted {
+                name: "command_line".to_string(),
+                url: Url::parse("https://abc123.ngrok.io").unwrap(),
+            })
+        );
+        assert_eq!(
+            parse(
+                r#"{"lvl":"eror","msg":"session closing","obj":"tunnels.session","err":"read tcp: connection reset by peer"}"#
+            ),
+            Some(AgentEvent::Reconnecting {
+                reason: "read tcp: connection reset by peer".to_string()
+            })
+        );
+        assert_eq!(
+            parse(
+                r#"{"lvl":"warn","msg":"failed to reconnect session","err":"rate limit exceeded"}"#
+            ),
+            Some(AgentEvent::RateLimited {
+                message: "rate limit exceeded".to_string()
+            })
+        );
+        assert_eq!(
+            parse(r#"{"lvl":"eror","msg":"failed to start tunnel","err":"invalid address"}"#),
+            Some(AgentEvent::Error {
+                message: "invalid address".to_string()
+            })
+        );
+        assert_eq!(parse(r#"{"lvl":"info","msg":"open config file"}"#), None);
+        assert_eq!(parse("not json"), None);
+    }
+
+    #[test]
+    fn test_subscribers() {
+        let subscribers = Subscribers::open();
+        let early = subscribers.subscribe();
+
+        subscribers.publish(AgentEvent::SessionEstablished);
+        let late = subscribers.subscribe();
+        subscribers.close();
+
+        assert_eq!(
+            early.iter().collect::<Vec<_>>(),
+            [AgentEvent::SessionEstablished]
+        );
+        assert_eq!(late.iter().count(), 0);
+        assert_eq!(Subscribers::default().subscribe().iter().count(), 0);
+    }
+
+    #[test]
+    fn test_follow_restarted_log() {
+        let subscribers = Subscribers::open();
+        let events = subscribers.subscribe();
+
+        // The first agent's log closes while it's restarted
+        subscribers.hold();
+        subscribers.close();
+        let restarted = subscribers.restarted();
+        subscribers.release();
+
+        restarted.publish(AgentEvent::SessionEstablished);
+        subscribers.publish(AgentEvent::SessionEstablished);
+        subscribers.close();
+        restarted.close();
+
+        assert_eq!(
+            events.iter().collect::<Vec<_>>(),
+            [AgentEvent::SessionEstablished]
+        );
+    }
+
+    #[test]
+    fn test_session_hooks() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (disconnects, reconnects) = (calls.clone(), calls.clone());
+        let hooks = SessionHooks {
+            on_disconnect: Some(Arc::new(move |reason: &str| {
+                disconnects
+                    .lock()
+                    .unwrap()
+                    .push(format!("disconnect: {}", reason))
+            })),
+            on_reconnect: Some(Arc::new(move || {
+                reconnects.lock().unwrap().push("reconnect".to_string())
+            })),
+        };
+
+        let reconnecting = |reason: &str| AgentEvent::Reconnecting {
+            reason: reason.to_string(),
+        };
+        let mut connected = true;
+        for event in [
+            AgentEvent::SessionEstablished,
+            reconnecting("connection reset"),
+            reconnecting("failed to dial"),
+            AgentEvent::SessionEstablished,
+        ] {
+            hooks.handle(&mut connected, &event);
+        }
+
+        assert_eq!(
+            *calls.lock().unwrap(),
+            ["disconnect: connection reset", "reconnect"]
+        );
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_event_stream() {
+        use futures_core::Stream;
+
+        let subscribers = Subscribers::open();
+        let mut stream = subscribers.stream();
+
+        subscribers.publish(AgentEvent::SessionEstablished);
+        subscribers.close();
+
+        let mut next = std::pin::Pin::new(&mut stream);
+        let events = [
+            std::future::poll_fn(|cx| next.as_mut().poll_next(cx)).await,
+            std::future::poll_fn(|cx| next.as_mut().poll_next(cx)).await,
+        ];
+        assert_eq!(events, [Some(AgentEvent::SessionEstablished), None]);
+    }
+}
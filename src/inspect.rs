@@ -0,0 +1,273 @@
+//! The traffic an agent recorded, from its inspection API at `/api/requests/http`.
+//! Fetch it with `Tunnel::captured_requests()`, e.g. to assert on exactly what hit the
+//! tunnel in an integration test.
+//!
+//! **Example**
+//!
+//! ```no_run
+//! let tunnel = ngrok::builder().https().port(3030).run()?;
+//!
+//! // ... send a webhook to the tunnel
+//!
+//! let requests = tunnel.captured_requests()?;
+//! assert_eq!(requests[0].method, "POST");
+//! assert_eq!(requests[0].header("content-type"), Some("application/json"));
+//! assert_eq!(requests[0].response.as_ref().map(|r| r.status), Some(200));
+//! # Ok::<(), ngrok::Error>(())
+//! ```
+
+use crate::api::AgentApi;
+use crate::Error;
+use base64::Engine;
+use serde_json::Value;
+use std::time::Duration;
+
+/// A request the agent forwarded through a tunnel, and the response if one was sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedRequest {
+    /// The agent's ID for the request, e.g. `548fb5c700000002`
+    pub id: String,
+    /// The name of the tunnel the request came through
+    pub tunnel_name: String,
+    /// The address of the client which sent the request
+    pub remote_addr: String,
+    /// How long the request took to be answered
+    pub duration: Duration,
+    /// The HTTP method, e.g. `POST`
+    pub method: String,
+    /// The path and query of the request, e.g. `/webhooks?source=github`
+    pub path: String,
+    /// The headers sorted by name, with a pair per value
+    pub headers: Vec<(String, String)>,
+    /// The body as sent by the client
+    pub body: Vec<u8>,
+    /// The response of the local server, unless none was sent
+    pub response: Option<CapturedResponse>,
+}
+
+/// The response to a `CapturedRequest`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedResponse {
+    /// The status code, e.g. `200`
+    pub status: u16,
+    /// The headers sorted by name, with a pair per value
+    pub headers: Vec<(String, String)>,
+    /// The body as sent by the local server
+    pub body: Vec<u8>,
+}
+
+impl CapturedRequest {
+    /// The first value of a header, matching its name in any case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// The body as text, replacing invalid UTF-8
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+impl CapturedResponse {
+    /// The first value of a header, matching its name in any case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    /// The body as text, replacing invalid UTF-8
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+/// The requests captured by the agent, oldest first, through the named tunnel or all
+/// of them
+pub(crate) fn captured_requests(
+    api: &AgentApi,
+    tunnel_name: Option<&str>,
+) -> Result<Vec<CapturedRequest>, Error> {
+    let query = Vec::from_iter(tunnel_name.map(|name| ("tunnel_name", name)));
+    let response = api.send("GET", "/api/requests/http", &query, None)?;
+    parse_requests(&response)
+}
+
+/// Delete the requests captured by the agent, through all of its tunnels
+pub(crate) fn clear_captured_requests(api: &AgentApi) -> Result<(), Error> {
+    api.delete("/api/requests/http")
+}
+
+/// Like `captured_requests`, through `reqwest`
+#[cfg(feature = "reqwest")]
+pub(crate) async fn captured_requests_async(
+    api: &AgentApi,
+    tunnel_name: Option<&str>,
+) -> Result<Vec<CapturedRequest>, Error> {
+    let query = Vec::from_iter(tunnel_name.map(|name| ("tunnel_name", name)));
+    let response = api.send_async("GET", "/api/requests/http", &query).await?;
+    parse_requests(&response)
+}
+
+/// Like `clear_captured_requests`, through `reqwest`
+#[cfg(feature = "reqwest")]
+pub(crate) async fn clear_captured_requests_async(api: &AgentApi) -> Result<(), Error> {
+    api.send_async("DELETE", "/api/requests/http", &[])
+        .await
+        .map(drop)
+}
+
+/// Parse the list of requests in a response of the inspection API. The agent lists
+/// the most recent request first.
+fn parse_requests(response: &Value) -> Result<Vec<CapturedRequest>, Error> {
+    let requests = response
+        .get("requests")
+        .and_then(|requests| requests.as_array())
+        .ok_or(Error::MalformedAPIResponse)?;
+
+    requests
+        .iter()
+        .rev()
+        .map(|request| parse_request(request).ok_or(Error::MalformedAPIResponse))
+        .collect()
+}
+
+fn parse_request(captured: &Value) -> Option<CapturedRequest> {
+    let field = |value: &Value, name| Some(value.get(name)?.as_str()?.to_string());
+    let request = captured.get("request")?;
+
+    let response = match captured.get("response") {
+        None | Some(Value::Null) => None,
+        Some(response) => Some(CapturedResponse {
+            status: response.get("status_code")?.as_u64()? as u16,
+            headers: parse_headers(response)?,
+            body: parse_body(response)?,
+        }),
+    };
+
+    Some(CapturedRequest {
+        id: field(captured, "id")?,
+        tunnel_name: field(captured, "tunnel_name").unwrap_or_default(),
+        remote_addr: field(captured, "remote_addr").unwrap_or_default(),
+        duration: Duration::from_nanos(captured.get("duration")?.as_u64()?),
+        method: field(request, "method")?,
+        path: field(request, "uri")?,
+        headers: parse_headers(request)?,
+        body: parse_body(request)?,
+        response,
+    })
+}
+
+/// Flatten the `headers` object, which maps each name to a list of values
+fn parse_headers(message: &Value) -> Option<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+
+    for (name, values) in message.get("headers")?.as_object()? {
+        for value in values.as_array()? {
+            headers.push((name.clone(), value.as_str()?.to_string()));
+        }
+    }
+
+    Some(headers)
+}
+
+/// The body of the base64 encoded `raw` HTTP message, after its headers
+fn parse_body(message: &Value) -> Option<Vec<u8>> {
+    let raw = message.get("raw")?.as_str()?;
+    let raw = base64::engine::general_purpose::STANDARD.decode(raw).ok()?;
+
+    let body = raw
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|end| raw[end + 4..].to_vec())
+        .unwrap_or_default();
+
+    Some(body)
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_requests() {
+        let encode = |raw: &str| base64::engine::general_purpose::STANDARD.encode(raw);
+        let response = json!({
+            "uri": "/api/requests/http",
+            "requests": [
+                {
+                    "id": "548fb5c700000003",
+                    "tunnel_name": "command_line",
+                    "remote_addr": "192.0.2.10",
+                    "duration": 1_500_000,
+                    "request": {
+                        "method": "GET",
+                        "uri": "/health",
+                        "headers": {},
+                        "raw": encode("GET /health HTTP/1.1\r\nHost: abc123.ngrok.io\r\n\r\n"),
+                    },
+                    "response": null,
+                },
+                {
+                    "id": "548fb5c700000002",
+                    "tunnel_name": "command_line",
+                    "remote_addr": "192.0.2.10",
+                    "duration": 3_893_202,
+                    "request": {
+                        "method": "POST",
+                        "uri": "/webhooks?source=github",
+                        "headers": {
+                            "Content-Type": ["application/json"],
+                            "X-Tag": ["a", "b"],
+                        },
+                        "raw": encode("POST /webhooks?source=github HTTP/1.1\r\nContent-Type: application/json\r\n\r\n{\"ok\":true}"),
+                    },
+                    "response": {
+                        "status_code": 201,
+                        "headers": { "Content-Length": ["7"] },
+                        "raw": encode("HTTP/1.1 201 Created\r\nContent-Length: 7\r\n\r\ncreated"),
+                    },
+                },
+            ],
+        });
+
+        let requests = parse_requests(&response).unwrap();
+        assert_eq!(requests.len(), 2);
+
+        let webhook = &requests[0];
+        assert_eq!(webhook.id, "548fb5c700000002");
+        assert_eq!(webhook.method, "POST");
+        assert_eq!(webhook.path, "/webhooks?source=github");
+        assert_eq!(webhook.duration, Duration::from_nanos(3_893_202));
+        assert_eq!(webhook.header("content-type"), Some("application/json"));
+        assert_eq!(
+            webhook
+                .headers
+                .iter()
+                .filter(|(name, _)| name == "X-Tag")
+                .count(),
+            2
+        );
+        assert_eq!(webhook.body_text(), r#"{"ok":true}"#);
+
+        let response = webhook.response.as_ref().unwrap();
+        assert_eq!(response.status, 201);
+        assert_eq!(response.header("Content-Length"), Some("7"));
+        assert_eq!(response.body, b"created");
+
+        assert_eq!(requests[1].path, "/health");
+        assert!(requests[1].body.is_empty());
+        assert_eq!(requests[1].response, None);
+
+        assert!(matches!(
+            parse_requests(&json!({ "requests": [{ "id": "1" }] })),
+            Err(Error::MalformedAPIResponse)
+        ));
+    }
+}
@@ -0,0 +1,351 @@
+//! Start a tunnel in the background while the caller gets on with other setup.
+
+use crate::{Builder, Error, StderrTail, Tunnel, WithPort};
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A tunnel whose agent is starting in the background, returned by `Builder::try_run()`.
+/// Dropping it, or the future it's awaited with, before the tunnel is up stops the agent.
+///
+/// **Example**
+///
+/// ```no_run
+/// use std::time::Duration;
+///
+/// let pending = ngrok::builder().port(3030).try_run();
+/// while !pending.is_ready() {
+///     // ... carry on with other setup ...
+/// #   std::thread::sleep(Duration::from_millis(100));
+/// }
+/// let tunnel = pending.wait()?;
+/// # Ok::<(), ngrok::Error>(())
+/// ```
+#[derive(Debug)]
+pub struct PendingTunnel {
+    startup: Startup,
+    receiver: Receiver<Result<Tunnel, Error>>,
+    /// The result once `is_ready()` has received it
+    result: Mutex<Option<Result<Tunnel, Error>>>,
+}
+
+impl PendingTunnel {
+    pub(crate) fn spawn(mut builder: Builder<WithPort>) -> Self {
+        let startup = Startup::default();
+        builder.options.startup = Some(startup.clone());
+
+        // A tunnel which comes up once nobody waits for it is dropped along with the
+        // unused result
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = sender.send(builder.run());
+        });
+
+        PendingTunnel {
+            startup,
+            receiver,
+            result: Mutex::new(None),
+        }
+    }
+
+    /// Whether the startup has finished, i.e. `wait()` returns the tunnel or the reason
+    /// it failed without blocking
+    pub fn is_ready(&self) -> bool {
+        let mut result = self.result.lock().unwrap();
+        if result.is_none() {
+            match self.receiver.try_recv() {
+                Ok(received) => *result = Some(received),
+                Err(TryRecvError::Disconnected) => *result = Some(Err(panicked())),
+                Err(TryRecvError::Empty) => {}
+            }
+        }
+        result.is_some()
+    }
+
+    /// Block until the tunnel is up or fails to start, within the builder's
+    /// `.ready_timeout()`
+    pub fn wait(self) -> Result<Tunnel, Error> {
+        if let Some(result) = self.result.lock().unwrap().take() {
+            return result;
+        }
+        self.receiver.recv().unwrap_or_else(|_| Err(panicked()))
+    }
+
+    /// Give up on the tunnel and stop its agent, e.g. when the surrounding test is
+    /// cancelled. Unlike dropping the handle, this returns once the agent is stopped.
+    pub fn abort(self) {
+        self.startup.cancel();
+
+        // The startup sends its result once it stopped the agent, and a tunnel which came
+        // up in the meantime stops it when dropped
+        if self.result.lock().unwrap().take().is_none() {
+            let _ = self.receiver.recv();
+        }
+    }
+
+    /// Like `wait()`, but give up once the timeout elapses. The agent is stopped and
+    /// `Error::Timeout` returns what it wrote to stderr so far.
+    pub fn wait_ready(self, timeout: Duration) -> Result<Tunnel, Error> {
+        if let Some(result) = self.result.lock().unwrap().take() {
+            return result;
+        }
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(self.startup.expire(timeout)),
+            Err(RecvTimeoutError::Disconnected) => Err(panicked()),
+        }
+    }
+}
+
+/// Wait for the tunnel like `wait()`, without blocking the async runtime
+///
+/// **Example**
+///
+/// ```no_run
+/// # async fn example() -> Result<(), ngrok::Error> {
+/// let pending = ngrok::builder().port(3030).try_run();
+/// let (tunnel, _) = tokio::join!(pending, async {
+///     // ... start the server on port 3030 ...
+/// });
+/// println!("{}", tunnel?);
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "async")]
+impl std::future::IntoFuture for PendingTunnel {
+    type Output = Result<Tunnel, Error>;
+    type IntoFuture =
+        std::pin::Pin<Box<dyn std::future::Future<Output = Self::Output> + Send + 'static>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        // The blocking task can't be cancelled, so cancel the startup once the future is dropped
+        let cancel_on_drop = CancelOnDrop(self.startup.clone());
+
+        Box::pin(async move {
+            let _cancel_on_drop = cancel_on_drop;
+            tokio::task::spawn_blocking(move || self.wait())
+                .await
+                .map_err(|err| Error::Io(io::Error::other(err)))?
+        })
+    }
+}
+
+impl Drop for PendingTunnel {
+    fn drop(&mut self) {
+        self.startup.cancel();
+    }
+}
+
+/// Cancels the startup once dropped
+#[cfg(feature = "async")]
+struct CancelOnDrop(Startup);
+
+#[cfg(feature = "async")]
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+fn panicked() -> Error {
+    Error::Io(io::Error::other("starting the tunnel panicked"))
+}
+
+/// Shared with the startup of a `PendingTunnel`, which stops the agent once it's
+/// cancelled
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Startup(Arc<Mutex<StartupState>>);
+
+#[derive(Debug, Default)]
+struct StartupState {
+    stderr: Option<StderrTail>,
+    cancelled: bool,
+}
+
+impl Startup {
+    /// Keep the agent's stderr for the error reported if the startup times out
+    pub(crate) fn watch(&self, stderr: &StderrTail) {
+        self.0.lock().unwrap().stderr = Some(stderr.clone());
+    }
+
+    pub(crate) fn cancelled(&self) -> bool {
+        self.0.lock().unwrap().cancelled
+    }
+
+    fn cancel(&self) {
+        self.0.lock().unwrap().cancelled = true;
+    }
+
+    /// Give up on the tunnel once the timeout elapsed
+    fn expire(&self, timeout: Duration) -> Error {
+        let mut state = self.0.lock().unwrap();
+        state.cancelled = true;
+        let stderr = state.stderr.as_ref().map(StderrTail::text);
+        Error::Timeout(timeout, stderr.unwrap_or_default())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use crate::tests::fake_agent;
+    use std::path::Path;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_try_run() {
+        let ngrok = fake_agent(
+            r#"sleep 0.3
+echo '{"msg":"started tunnel","addr":"http://localhost:3030","url":"https://pending.ngrok.io"}'
+exec sleep 30"#,
+        );
+
+        let pending = crate::builder()
+            .executable(&ngrok)
+            .authtoken("fake")
+            .port(3030)
+            .try_run();
+        assert!(!pending.is_ready());
+
+        let tunnel = pending.wait_ready(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            tunnel.current_url().unwrap().as_str(),
+            "https://pending.ngrok.io/"
+        );
+    }
+
+    #[test]
+    fn test_abort() {
+        let ngrok = fake_agent(
+            r#"echo $$ > "$(dirname "$0")/pid.tmp"; mv "$(dirname "$0")/pid.tmp" "$(dirname "$0")/pid"; exec sleep 30"#,
+        );
+        let pid_file = ngrok.with_file_name("pid");
+
+        let pending = crate::builder()
+            .executable(&ngrok)
+            .authtoken("fake")
+            .port(3030)
+            .try_run();
+
+        let started_at = Instant::now();
+        while !pid_file.exists() {
+            assert!(started_at.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        pending.abort();
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        assert!(
+            !Path::new(&format!("/proc/{}", pid.trim())).exists() || cfg!(not(target_os = "linux"))
+        );
+    }
+
+    #[test]
+    fn test_abort_hung_startup() {
+        // `ngrok version` hangs, and then the shared agent's API never comes up
+        for hung_version in [true, false] {
+            let ngrok = fake_agent(r#"echo $$ > "$(dirname "$0")/pid"; exec sleep 30"#);
+            if hung_version {
+                let script = "#!/bin/sh\necho $$ > \"$(dirname \"$0\")/pid\"; exec sleep 30\n";
+                std::fs::write(&ngrok, script).unwrap();
+            }
+            let pid_file = ngrok.with_file_name("pid");
+
+            let pending = crate::builder()
+                .executable(&ngrok)
+                .authtoken("fake")
+                .shared_agent()
+                .port(3030)
+                .try_run();
+
+            let started_at = Instant::now();
+            while !pid_file.exists() {
+                assert!(started_at.elapsed() < Duration::from_secs(5));
+                thread::sleep(Duration::from_millis(20));
+            }
+
+            let started_at = Instant::now();
+            pending.abort();
+            assert!(started_at.elapsed() < Duration::from_secs(1));
+            // A dropped agent is reaped in the background
+            let pid = std::fs::read_to_string(&pid_file).unwrap();
+            while Path::new(&format!("/proc/{}", pid.trim())).exists() && cfg!(target_os = "linux")
+            {
+                assert!(started_at.elapsed() < Duration::from_secs(1));
+                thread::sleep(Duration::from_millis(20));
+            }
+        }
+    }
+
+    #[test]
+    fn test_timeout_waiting_until_ready() {
+        let ngrok = fake_agent(
+            r#"echo $$ > "$(dirname "$0")/pid"
+echo '{"msg":"started tunnel","addr":"http://localhost:3030","url":"https://pending.ngrok.io"}'
+exec sleep 30"#,
+        );
+
+        let result = crate::builder()
+            .executable(&ngrok)
+            .authtoken("fake")
+            .ready_when(|_| false)
+            .port(3030)
+            .try_run()
+            .wait_ready(Duration::from_millis(500));
+        assert!(matches!(result, Err(crate::Error::Timeout(..))));
+
+        // The agent is stopped rather than left running until the ready timeout
+        let pid = std::fs::read_to_string(ngrok.with_file_name("pid")).unwrap();
+        let started_at = Instant::now();
+        while Path::new(&format!("/proc/{}", pid.trim())).exists() && cfg!(target_os = "linux") {
+            assert!(started_at.elapsed() < Duration::from_secs(3));
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_abort_waiting_for_backend() {
+        let ngrok = fake_agent(r#"touch "$(dirname "$0")/spawned"; exec sleep 30"#);
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let pending = crate::builder()
+            .executable(&ngrok)
+            .authtoken("fake")
+            .wait_for_backend(Duration::from_secs(30))
+            .port(port)
+            .try_run();
+        thread::sleep(Duration::from_millis(200));
+
+        let started_at = Instant::now();
+        pending.abort();
+        assert!(started_at.elapsed() < Duration::from_secs(1));
+        assert!(!ngrok.with_file_name("spawned").exists());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_await_pending() {
+        let ngrok = fake_agent(
+            r#"echo '{"msg":"started tunnel","addr":"http://localhost:3030","url":"https://pending.ngrok.io"}'
+exec sleep 30"#,
+        );
+
+        let pending = crate::builder()
+            .executable(&ngrok)
+            .authtoken("fake")
+            .port(3030)
+            .try_run();
+        let (tunnel, ready) = tokio::join!(pending, async { "server" });
+        assert_eq!(ready, "server");
+        assert_eq!(
+            tunnel.unwrap().current_url().unwrap().as_str(),
+            "https://pending.ngrok.io/"
+        );
+    }
+}
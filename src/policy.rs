@@ -0,0 +1,275 @@
+//! Typed builders for `ngrok` [traffic policies](https://ngrok.com/docs/traffic-policy/).
+//! A `Policy` is serialized to a policy file and attached to the tunnel with
+//! `Builder::traffic_policy()`, which requires a v3 `ngrok` agent.
+//!
+//! **Example**
+//!
+//! ```
+//! use ngrok::policy::{Action, Policy, Rule};
+//! use std::time::Duration;
+//!
+//! let policy = Policy::new()
+//!     .on_http_request(
+//!         Rule::new()
+//!             .name("Rate limit the API")
+//!             .expression("req.url.path.startsWith('/api')")
+//!             .action(Action::RateLimit {
+//!                 name: "api".to_string(),
+//!                 capacity: 10,
+//!                 rate: Duration::from_secs(60),
+//!             }),
+//!     )
+//!     .on_http_response(Rule::new().action(Action::AddHeaders(vec![(
+//!         "x-served-by".to_string(),
+//!         "ngrok".to_string(),
+//!     )])));
+//! ```
+
+use serde_json::{json, Map, Value};
+use std::time::Duration;
+
+/// A traffic policy, made of rules which run in order during each phase of a connection
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    on_http_request: Vec<Rule>,
+    on_http_response: Vec<Rule>,
+    on_tcp_connect: Vec<Rule>,
+}
+
+impl Policy {
+    /// Create an empty `Policy`
+    pub fn new() -> Self {
+        Policy {
+            ..Default::default()
+        }
+    }
+
+    /// Add a rule that runs for each HTTP request before it is forwarded to your service
+    pub fn on_http_request(mut self, rule: Rule) -> Self {
+        self.on_http_request.push(rule);
+        self
+    }
+
+    /// Add a rule that runs for each HTTP response before it is returned to the client
+    pub fn on_http_response(mut self, rule: Rule) -> Self {
+        self.on_http_response.push(rule);
+        self
+    }
+
+    /// Add a rule that runs when a TCP connection is established
+    pub fn on_tcp_connect(mut self, rule: Rule) -> Self {
+        self.on_tcp_connect.push(rule);
+        self
+    }
+
+    /// Serialize the policy into the JSON form of a policy file
+    pub fn to_json(&self) -> String {
+        self.to_value().to_string()
+    }
+
+    pub(crate) fn to_value(&self) -> Value {
+        let mut phases = Map::new();
+
+        for (phase, rules) in [
+            ("on_http_request", &self.on_http_request),
+            ("on_http_response", &self.on_http_response),
+            ("on_tcp_connect", &self.on_tcp_connect),
+        ] {
+            if !rules.is_empty() {
+                let rules = rules.iter().map(Rule::to_value).collect();
+                phases.insert(phase.to_string(), Value::Array(rules));
+            }
+        }
+
+        Value::Object(phases)
+    }
+}
+
+/// A policy rule: its actions run when every expression matches
+#[derive(Debug, Clone, Default)]
+pub struct Rule {
+    name: Option<String>,
+    expressions: Vec<String>,
+    actions: Vec<Action>,
+}
+
+impl Rule {
+    /// Create a `Rule` which, without expressions, matches every request
+    pub fn new() -> Self {
+        Rule {
+            ..Default::default()
+        }
+    }
+
+    /// Name the rule, which is shown in the `ngrok` dashboard
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_string());
+        self
+    }
+
+    /// Only run the actions when this CEL expression matches, e.g. `req.method == 'POST'`
+    pub fn expression(mut self, expression: &str) -> Self {
+        self.expressions.push(expression.to_string());
+        self
+    }
+
+    /// Add an action to run when the rule matches
+    pub fn action(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    fn to_value(&self) -> Value {
+        let mut rule = Map::new();
+
+        if let Some(name) = &self.name {
+            rule.insert("name".to_string(), json!(name));
+        }
+        if !self.expressions.is_empty() {
+            rule.insert("expressions".to_string(), json!(self.expressions));
+        }
+        let actions = self.actions.iter().map(Action::to_value).collect();
+        rule.insert("actions".to_string(), Value::Array(actions));
+
+        Value::Object(rule)
+    }
+}
+
+/// An action taken by a matching `Rule`
+#[derive(Debug, Clone)]
+pub enum Action {
+    /// Allow `capacity` requests per `rate` window from each client IP and reject the rest
+    RateLimit {
+        name: String,
+        capacity: u64,
+        rate: Duration,
+    },
+    /// Add headers to the request or response
+    AddHeaders(Vec<(String, String)>),
+    /// Remove headers from the request or response
+    RemoveHeaders(Vec<String>),
+    /// Require a bearer JWT in the `Authorization` header, signed by a key from the
+    /// JWKS URL and issued by `issuer` for `audience`
+    JwtValidation {
+        issuer: String,
+        audience: String,
+        jwks_url: String,
+    },
+    /// Reject the request or connection with the given HTTP status code
+    Deny { status_code: u16 },
+    /// Respond directly from `ngrok` without forwarding the request
+    CustomResponse { status_code: u16, body: String },
+    /// Any other action, given its type, e.g. `url-rewrite`, and JSON configuration
+    Custom { kind: String, config: Value },
+}
+
+impl Action {
+    fn to_value(&self) -> Value {
+        let (kind, config) = match self {
+            Action::RateLimit {
+                name,
+                capacity,
+                rate,
+            } => (
+                "rate-limit",
+                json!({
+                    "name": name,
+                    "algorithm": "sliding_window",
+                    "capacity": capacity,
+                    "rate": duration(*rate),
+                    "bucket_key": ["conn.client_ip"],
+                }),
+            ),
+            Action::AddHeaders(headers) => {
+                let headers: Map<String, Value> = headers
+                    .iter()
+                    .map(|(name, value)| (name.clone(), json!(value)))
+                    .collect();
+                ("add-headers", json!({ "headers": headers }))
+            }
+            Action::RemoveHeaders(headers) => ("remove-headers", json!({ "headers": headers })),
+            Action::JwtValidation {
+                issuer,
+                audience,
+                jwks_url,
+            } => (
+                "jwt-validation",
+                json!({
+                    "issuer": { "allow_list": [{ "value": issuer }] },
+                    "audience": { "allow_list": [{ "value": audience }] },
+                    "http": {
+                        "tokens": [{
+                            "type": "jwt",
+                            "method": "header",
+                            "name": "Authorization",
+                            "prefix": "Bearer "
+                        }]
+                    },
+                    "jws": {
+                        "allowed_algorithms": ["RS256", "ES256"],
+                        "keys": { "sources": { "additional_jkus": [jwks_url] } }
+                    }
+                }),
+            ),
+            Action::Deny { status_code } => ("deny", json!({ "status_code": status_code })),
+            Action::CustomResponse { status_code, body } => (
+                "custom-response",
+                json!({ "status_code": status_code, "body": body }),
+            ),
+            Action::Custom { kind, config } => (kind.as_str(), config.clone()),
+        };
+
+        json!({ "type": kind, "config": config })
+    }
+}
+
+/// Render a duration like Go does, e.g. `60s`, or `500ms` when it isn't whole seconds
+fn duration(duration: Duration) -> String {
+    match duration.subsec_millis() {
+        0 => format!("{}s", duration.as_secs()),
+        _ => format!("{}ms", duration.as_millis()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policy_to_json() {
+        let policy = Policy::new().on_http_request(
+            Rule::new()
+                .name("deny posts")
+                .expression("req.method == 'POST'")
+                .action(Action::Deny { status_code: 405 }),
+        );
+
+        let value: Value = serde_json::from_str(&policy.to_json()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "on_http_request": [{
+                    "name": "deny posts",
+                    "expressions": ["req.method == 'POST'"],
+                    "actions": [{ "type": "deny", "config": { "status_code": 405 } }]
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_rate_limit_duration() {
+        let rate_limit = |rate| Action::RateLimit {
+            name: "limit".to_string(),
+            capacity: 10,
+            rate,
+        };
+
+        let value = rate_limit(Duration::from_secs(60)).to_value();
+        assert_eq!(value["config"]["rate"], "60s");
+        let value = rate_limit(Duration::from_millis(500)).to_value();
+        assert_eq!(value["config"]["rate"], "500ms");
+        let value = rate_limit(Duration::from_millis(1500)).to_value();
+        assert_eq!(value["config"]["rate"], "1500ms");
+    }
+}
@@ -0,0 +1,174 @@
+//! Open several tunnels on one agent at once, rather than waiting for each in turn.
+
+use crate::{Agent, Builder, Error, Tunnel, WithPort};
+use std::thread;
+
+/// Several tunnels started concurrently on one agent, which is stopped once they're
+/// all dropped. Each tunnel takes a few seconds to come up, so this is much faster
+/// than opening them one by one with `Agent::tunnel()`.
+///
+/// **Example**
+///
+/// ```no_run
+/// use ngrok::TunnelSet;
+///
+/// let tunnels = TunnelSet::new(ngrok::builder().authtoken("..."))
+///     .tunnel(ngrok::builder().https().port(3000))
+///     .tunnel(ngrok::builder().https().port(4000))
+///     .tunnel(ngrok::builder().tcp().port(5432))
+///     .start()?;
+///
+/// for tunnel in &tunnels {
+///     println!("{}", tunnel);
+/// }
+/// # Ok::<(), ngrok::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct TunnelSet {
+    agent: Builder,
+    tunnels: Vec<Builder<WithPort>>,
+}
+
+impl TunnelSet {
+    /// An empty set, whose agent is started with the builder's agent-wide options
+    /// like `Agent::start()`
+    pub fn new(agent: Builder) -> Self {
+        TunnelSet {
+            agent,
+            tunnels: Vec::new(),
+        }
+    }
+
+    /// Add the builder's tunnel to the set. Its agent-wide options are ignored.
+    pub fn tunnel(mut self, builder: Builder<WithPort>) -> Self {
+        self.tunnels.push(builder);
+        self
+    }
+
+    /// Start the agent and every tunnel, in the order they were added. If any of them
+    /// fails the first error is returned, and the others are stopped.
+    pub fn start(self) -> Result<Vec<Tunnel>, Error> {
+        self.start_each()?.into_iter().collect()
+    }
+
+    /// Start the agent and every tunnel, reporting each one's result in the order they
+    /// were added, e.g. to carry on without a tunnel whose domain was rejected. Only a
+    /// failure to start the agent fails the whole set.
+    pub fn start_each(self) -> Result<Vec<Result<Tunnel, Error>>, Error> {
+        let agent = Agent::start(self.agent)?;
+
+        let results = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .tunnels
+                .into_iter()
+                .map(|builder| {
+                    let agent = &agent;
+                    scope.spawn(move || agent.tunnel(builder))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("opening a tunnel panicked"))
+                .collect()
+        });
+
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ApiResponse, ApiTransport};
+    use serde_json::json;
+    use std::sync::{Arc, Mutex};
+    use url::Url;
+
+    /// Opens TCP tunnels through the API except those to port 4000, whose domain is
+    /// rejected, recording the method and path of each request
+    #[derive(Debug, Clone, Default)]
+    struct RejectsPort4000 {
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl ApiTransport for RejectsPort4000 {
+        fn send(
+            &self,
+            method: &str,
+            url: &Url,
+            body: Option<&serde_json::Value>,
+        ) -> Result<ApiResponse, Error> {
+            let request = format!("{} {}", method, url.path());
+            self.requests.lock().unwrap().push(request);
+
+            let response = |status, body: serde_json::Value| ApiResponse {
+                status,
+                body: body.to_string(),
+            };
+            Ok(match (method, body) {
+                ("POST", Some(config)) if config["addr"].to_string().contains("4000") => {
+                    response(400, json!({ "msg": "domain rejected" }))
+                }
+                ("POST", Some(config)) => response(
+                    201,
+                    json!({
+                        "name": config["name"],
+                        "proto": "tcp",
+                        "public_url": format!("tcp://{}.ngrok.io:1", config["name"].as_str().unwrap()),
+                        "config": { "addr": config["addr"] },
+                    }),
+                ),
+                ("DELETE", _) => response(204, serde_json::Value::Null),
+                _ => response(200, json!({ "tunnels": [] })),
+            })
+        }
+    }
+
+    #[cfg(unix)]
+    fn tunnel_set(ngrok: &std::path::Path, transport: &RejectsPort4000) -> TunnelSet {
+        TunnelSet::new(
+            crate::builder()
+                .executable(ngrok)
+                .authtoken("fake")
+                .api_transport(transport.clone()),
+        )
+        .tunnel(crate::builder().tcp().port(3000))
+        .tunnel(crate::builder().tcp().port(4000))
+        .tunnel(crate::builder().tcp().port(5000))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start_each() {
+        let ngrok = crate::tests::fake_agent("exec sleep 30");
+        let transport = RejectsPort4000::default();
+
+        let results = tunnel_set(&ngrok, &transport).start_each().unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(matches!(&results[1], Err(Error::Api(msg)) if msg == "domain rejected"));
+        assert!(results[2].is_ok());
+
+        std::fs::remove_dir_all(ngrok.parent().unwrap()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start_stops_started_tunnels() {
+        let ngrok = crate::tests::fake_agent("exec sleep 30");
+        let transport = RejectsPort4000::default();
+
+        let err = tunnel_set(&ngrok, &transport).start().unwrap_err();
+        assert!(matches!(&err, Error::Api(msg) if msg == "domain rejected"));
+
+        // Both tunnels which came up are removed from the agent
+        let requests = transport.requests.lock().unwrap().clone();
+        let deletes = requests
+            .iter()
+            .filter(|request| request.starts_with("DELETE "));
+        assert_eq!(deletes.count(), 2);
+
+        std::fs::remove_dir_all(ngrok.parent().unwrap()).unwrap();
+    }
+}
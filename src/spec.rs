@@ -0,0 +1,219 @@
+//! Tunnel settings kept in a file, e.g. a checked-in `ngrok.toml`, rather than in code.
+//! A `TunnelSpec` deserializes from any `serde` format and turns into a `Builder` with
+//! `Builder::from_spec()`.
+//!
+//! **Example**
+//!
+//! ```
+//! use ngrok::spec::TunnelSpec;
+//!
+//! let spec: TunnelSpec = toml::from_str(
+//!     r#"
+//!     proto = "http"
+//!     addr = "3030"
+//!     domain = "dev.example.com"
+//!     region = "eu"
+//!     basic_auth = { username = "admin", password = "hunter2" }
+//!     "#,
+//! )
+//! .unwrap();
+//!
+//! let builder = ngrok::Builder::from_spec(&spec)?;
+//! # Ok::<(), ngrok::Error>(())
+//! ```
+
+use crate::{Builder, Error, HostHeader, Protocol, Region, Scheme, Secret, TunnelAddr, WithPort};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// The settings of a tunnel, named after the `Builder` methods which apply them. Only
+/// `addr` is required; anything else left out keeps the builder's default.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TunnelSpec {
+    /// `http`, `tcp` or `tls`, see `Builder::proto()`
+    pub proto: Option<Protocol>,
+    /// The local address, e.g. `3030`, `192.168.1.10:8080` or `unix:///tmp/app.sock`,
+    /// as understood by `TunnelAddr`
+    pub addr: Option<String>,
+    /// The path of the agent, see `Builder::executable()`
+    pub executable: Option<PathBuf>,
+    /// Agent configs passed with `--config`, see `Builder::config_file()`
+    pub config_files: Vec<PathBuf>,
+    /// See `Builder::authtoken()`
+    pub authtoken: Option<Secret>,
+    /// e.g. `eu`, see `Builder::region()`
+    pub region: Option<Region>,
+    /// The address of the agent's web interface, see `Builder::web_addr()`
+    pub web_addr: Option<SocketAddr>,
+    /// See `Builder::subdomain()`
+    pub subdomain: Option<String>,
+    /// A custom domain, see `Builder::domain()`
+    pub domain: Option<String>,
+    /// A full public URL, see `Builder::url()`
+    pub url: Option<String>,
+    /// A reserved TCP address, see `Builder::remote_addr()`
+    pub remote_addr: Option<String>,
+    /// `https`, `http` or `http_and_https`, see `Builder::scheme()`
+    pub scheme: Option<Scheme>,
+    /// See `Builder::basic_auth()`
+    pub basic_auth: Option<BasicAuth>,
+    /// See `Builder::allow_cidr()`
+    pub allow_cidrs: Vec<IpNet>,
+    /// See `Builder::deny_cidr()`
+    pub deny_cidrs: Vec<IpNet>,
+    /// `rewrite`, `preserve` or a custom value, see `HostHeader`
+    pub host_header: Option<String>,
+    /// Whether the agent captures requests, see `Builder::inspect()`
+    pub inspect: Option<bool>,
+    /// See `Builder::metadata()`
+    pub metadata: Option<String>,
+    /// The labels of a labeled tunnel, see `Builder::label()`
+    pub labels: BTreeMap<String, String>,
+}
+
+/// The credentials of `Builder::basic_auth()`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BasicAuth {
+    /// The user name clients authenticate with
+    pub username: String,
+    /// Redacted when the spec is printed, see `Secret`
+    pub password: Secret,
+}
+
+impl Builder {
+    /// A builder with the spec's settings, e.g. one read from `ngrok.toml`. Fails unless
+    /// the spec has an `addr`.
+    pub fn from_spec(spec: &TunnelSpec) -> Result<Builder<WithPort>, Error> {
+        let mut builder = Builder::new();
+
+        if let Some(proto) = spec.proto {
+            builder = builder.proto(proto);
+        }
+        if let Some(executable) = &spec.executable {
+            builder = builder.executable(executable);
+        }
+        for path in &spec.config_files {
+            builder = builder.config_file(path);
+        }
+        if let Some(authtoken) = &spec.authtoken {
+            builder = builder.authtoken(authtoken.expose());
+        }
+        if let Some(region) = spec.region {
+            builder = builder.region(region);
+        }
+        if let Some(web_addr) = spec.web_addr {
+            builder = builder.web_addr(web_addr);
+        }
+        if let Some(subdomain) = &spec.subdomain {
+            builder = builder.subdomain(subdomain);
+        }
+        if let Some(domain) = &spec.domain {
+            builder = builder.domain(domain);
+        }
+        if let Some(url) = &spec.url {
+            builder = builder.url(url);
+        }
+        if let Some(remote_addr) = &spec.remote_addr {
+            builder = builder.remote_addr(remote_addr);
+        }
+        if let Some(scheme) = spec.scheme {
+            builder = builder.scheme(scheme);
+        }
+        if let Some(BasicAuth { username, password }) = &spec.basic_auth {
+            builder = builder.basic_auth(username, password.expose());
+        }
+        for cidr in &spec.allow_cidrs {
+            builder = builder.allow_cidr(*cidr);
+        }
+        for cidr in &spec.deny_cidrs {
+            builder = builder.deny_cidr(*cidr);
+        }
+        if let Some(host_header) = &spec.host_header {
+            let host_header = match host_header.as_str() {
+                "rewrite" => HostHeader::Rewrite,
+                "preserve" => HostHeader::Preserve,
+                host => HostHeader::Custom(host.to_string()),
+            };
+            builder = builder.host_header(host_header);
+        }
+        if let Some(inspect) = spec.inspect {
+            builder = builder.inspect(inspect);
+        }
+        if let Some(metadata) = &spec.metadata {
+            builder = builder.metadata(metadata);
+        }
+        for (key, value) in &spec.labels {
+            builder = builder.label(key, value);
+        }
+
+        let addr = spec
+            .addr
+            .as_deref()
+            .ok_or(Error::Builder("an `addr` in the tunnel spec"))?;
+
+        Ok(builder.addr(TunnelAddr::from(addr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AgentVersion;
+
+    #[test]
+    fn test_from_spec() {
+        let spec: TunnelSpec = toml::from_str(
+            r#"
+            proto = "tcp"
+            addr = "5432"
+            remote_addr = "1.tcp.ngrok.io:20301"
+            region = "eu"
+            allow_cidrs = ["10.0.0.0/8"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(spec.proto, Some(Protocol::Tcp));
+
+        let args = Builder::from_spec(&spec)
+            .unwrap()
+            .args(AgentVersion::V3, &Default::default())
+            .unwrap();
+        assert_eq!(args[0], "tcp");
+        assert!(args.contains(&"--region=eu".to_string()));
+        assert!(args.contains(&"--remote-addr=1.tcp.ngrok.io:20301".to_string()));
+        assert!(args.contains(&"--cidr-allow=10.0.0.0/8".to_string()));
+        assert_eq!(args.last().unwrap(), "5432");
+
+        let spec: TunnelSpec = toml::from_str(
+            r#"
+            addr = "3030"
+            scheme = "http_and_https"
+            host_header = "rewrite"
+            "#,
+        )
+        .unwrap();
+        let args = Builder::from_spec(&spec)
+            .unwrap()
+            .args(AgentVersion::V3, &Default::default())
+            .unwrap();
+        assert_eq!(
+            args,
+            [
+                "http",
+                "--host-header=rewrite",
+                "--scheme=http,https",
+                "3030"
+            ]
+        );
+
+        assert!(matches!(
+            Builder::from_spec(&TunnelSpec::default()),
+            Err(Error::Builder(_))
+        ));
+        assert!(toml::from_str::<TunnelSpec>("adr = \"3030\"").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Restart a tunnel's agent when it crashes, for long-running environments such as
+//! demos where nobody is around to notice.
+
+use crate::{Backoff, Builder, Error, Tunnel, WithPort};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How often a supervised agent is checked for having exited
+const SUPERVISE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Starts a tunnel and restarts its agent with `Tunnel::restart()` whenever it exits
+/// without the tunnel being stopped. The `Tunnel` is updated in place: its public URL
+/// and events follow the new agent and its status reports the running one.
+///
+/// **Example**
+///
+/// ```no_run
+/// use ngrok::{Backoff, Supervisor};
+/// use std::time::Duration;
+///
+/// let tunnel = Supervisor::new(ngrok::builder().https().port(3030))
+///     .max_restarts(3)
+///     .backoff(Backoff::new(Duration::from_secs(1)))
+///     .start()?;
+/// # Ok::<(), ngrok::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Supervisor {
+    builder: Builder<WithPort>,
+    max_restarts: usize,
+    backoff: Backoff,
+}
+
+impl Supervisor {
+    /// Supervise the builder's tunnel, restarting its agent up to 3 times
+    pub fn new(builder: Builder<WithPort>) -> Self {
+        Supervisor {
+            builder,
+            max_restarts: 3,
+            backoff: Backoff::default(),
+        }
+    }
+
+    /// Set how many times the agent is restarted before the tunnel is left exited.
+    /// Failed attempts to restart it count too.
+    pub fn max_restarts(mut self, max_restarts: usize) -> Self {
+        self.max_restarts = max_restarts;
+        self
+    }
+
+    /// Set the delays before restarting the agent. They grow with each restart.
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Start the tunnel like `Builder::run()` and supervise its agent from a watcher
+    /// thread until the tunnel is stopped or every clone of it is dropped
+    pub fn start(self) -> Result<Tunnel, Error> {
+        let tunnel = self.builder.run()?;
+        let supervised = tunnel.clone();
+        // Subscribers to the events aren't disconnected when the agent crashes
+        tunnel.events.hold();
+
+        thread::spawn(move || {
+            let tunnel = supervised;
+            let mut delays = self.backoff.delays();
+            let mut restarts = 0;
+
+            loop {
+                thread::sleep(SUPERVISE_POLL_INTERVAL);
+
+                // Every other clone of the tunnel was dropped, so it's no longer needed
+                if Arc::strong_count(&tunnel.proc) == 1 {
+                    break;
+                }
+
+                if tunnel.stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if !matches!(tunnel.proc.lock().unwrap().try_wait(), Ok(Some(_))) {
+                    continue;
+                }
+
+                if restarts == self.max_restarts {
+                    break;
+                }
+                restarts += 1;
+                #[cfg(feature = "tracing")]
+                tracing::warn!(restarts, "the agent exited, restarting it");
+                thread::sleep(delays.next_delay());
+
+                let _ = tunnel.restart();
+            }
+
+            tunnel.events.release();
+        });
+
+        Ok(tunnel)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::events::AgentEvent;
+    use crate::tests::fake_agent;
+    use std::time::Instant;
+
+    #[test]
+    fn test_restart_crashed_agent() {
+        // Each run logs a URL of its own and the first one crashes
+        let ngrok = fake_agent(
+            r#"dir=$(dirname "$0"); n=$(ls "$dir" | grep -c run); touch "$dir/run$n"
+echo "{\"msg\":\"started tunnel\",\"addr\":\"http://localhost:3030\",\"url\":\"https://run$n.ngrok.io\"}"
+[ "$n" = 0 ] && { sleep 0.5; exit 1; }
+echo '{"msg":"client session established"}'
+exec sleep 30"#,
+        );
+
+        let tunnel = Supervisor::new(
+            crate::builder()
+                .executable(&ngrok)
+                .authtoken("fake")
+                .port(3030),
+        )
+        .backoff(Backoff::fixed(Duration::from_millis(10)))
+        .start()
+        .unwrap();
+        assert_eq!(
+            tunnel.current_url().unwrap().as_str(),
+            "https://run0.ngrok.io/"
+        );
+        let events = tunnel.events();
+
+        let started_at = Instant::now();
+        while tunnel.current_url_unchecked().as_str() != "https://run1.ngrok.io/" {
+            assert!(started_at.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert!(tunnel.status().is_ok());
+        let mut events_after =
+            std::iter::from_fn(|| events.recv_timeout(Duration::from_secs(5)).ok());
+        assert!(events_after.any(|event| event == AgentEvent::SessionEstablished));
+
+        // A stopped tunnel stays stopped
+        tunnel.stop().unwrap();
+        thread::sleep(SUPERVISE_POLL_INTERVAL * 3);
+        assert!(tunnel.status().is_err());
+        // and its events end with the agent
+        assert!(events.recv_timeout(Duration::from_secs(5)).is_err());
+
+        std::fs::remove_dir_all(ngrok.parent().unwrap()).unwrap();
+    }
+}